@@ -0,0 +1,173 @@
+use crate::transport::DeviceTransport;
+use goblin::elf::{
+    header::{
+        machine_to_str, EI_CLASS, ELFCLASS64, ELFMAG, EM_386, EM_AARCH64, EM_ARM, EM_RISCV,
+        EM_X86_64, SELFMAG,
+    },
+    Elf,
+};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+/// Size of the ELF header of a 64-bit file, the larger of both classes.
+const ELF_HEADER_SIZE: u64 = 64;
+
+/// Machine and word size of a native binary, or of code a device can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Arch {
+    pub machine: u16,
+    pub is_64: bool,
+}
+
+impl Arch {
+    pub const AARCH64: Arch = Arch::new(EM_AARCH64, true);
+    pub const ARM: Arch = Arch::new(EM_ARM, false);
+    pub const X86_64: Arch = Arch::new(EM_X86_64, true);
+    pub const X86: Arch = Arch::new(EM_386, false);
+    pub const RISCV64: Arch = Arch::new(EM_RISCV, true);
+
+    const fn new(machine: u16, is_64: bool) -> Self {
+        Arch { machine, is_64 }
+    }
+
+    /// Architecture of the ELF file at `path`, `None` if it isn't an ELF file.
+    pub fn of_file(path: &Path) -> io::Result<Option<Self>> {
+        let mut bytes = Vec::new();
+        File::open(path)?
+            .take(ELF_HEADER_SIZE)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() < SELFMAG || &bytes[..SELFMAG] != ELFMAG {
+            return Ok(None);
+        }
+        let header = Elf::parse_header(&bytes)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        Ok(Some(Arch::new(
+            header.e_machine,
+            header.e_ident[EI_CLASS] == ELFCLASS64,
+        )))
+    }
+
+    /// Architecture of an ABI name of `const.product.cpu.abilist`.
+    pub fn from_abi(abi: &str) -> Option<Self> {
+        match abi.trim() {
+            "arm64-v8a" => Some(Arch::AARCH64),
+            "armeabi-v7a" | "armeabi" => Some(Arch::ARM),
+            "x86_64" => Some(Arch::X86_64),
+            "x86" => Some(Arch::X86),
+            "riscv64" => Some(Arch::RISCV64),
+            _ => None,
+        }
+    }
+
+    /// Architectures a kernel reporting `machine` from `uname -m` runs, 32-bit code
+    /// included on 64-bit kernels.
+    pub fn from_uname(machine: &str) -> Vec<Self> {
+        match machine.trim() {
+            "aarch64" | "arm64" => vec![Arch::AARCH64, Arch::ARM],
+            machine if machine.starts_with("arm") => vec![Arch::ARM],
+            "x86_64" => vec![Arch::X86_64, Arch::X86],
+            "i386" | "i686" => vec![Arch::X86],
+            "riscv64" => vec![Arch::RISCV64],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}-bit",
+            machine_to_str(self.machine),
+            if self.is_64 { 64 } else { 32 }
+        )
+    }
+}
+
+/// Shared libraries the ELF file at `path` links against, its `DT_NEEDED` entries.
+pub fn needed_libraries(path: &Path) -> io::Result<Vec<String>> {
+    let bytes = fs::read(path)?;
+    let elf =
+        Elf::parse(&bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    Ok(elf.libraries.iter().map(|name| name.to_string()).collect())
+}
+
+/// What the device says about its ABI and the architectures it runs, from
+/// `const.product.cpu.abilist` or else `uname -m`. `None` if neither tells.
+pub fn device_archs(
+    transport: &dyn DeviceTransport,
+    connect_key: &str,
+) -> Option<(String, Vec<Arch>)> {
+    let query = |command: &[&str]| {
+        transport
+            .shell(connect_key, command)
+            .ok()
+            .filter(|output| output.success())
+            .map(|output| output.stdout.trim().to_owned())
+    };
+
+    if let Some(abilist) = query(&["param", "get", "const.product.cpu.abilist"]) {
+        let archs: Vec<_> = abilist.split(',').filter_map(Arch::from_abi).collect();
+        if !archs.is_empty() {
+            return Some((abilist, archs));
+        }
+    }
+    let machine = query(&["uname", "-m"])?;
+    let archs = Arch::from_uname(&machine);
+    (!archs.is_empty()).then_some((machine, archs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_abi_knows_the_abilist_names() {
+        let archs: Vec<_> = "arm64-v8a,armeabi-v7a, armeabi,x86_64,x86,riscv64,mips"
+            .split(',')
+            .map(Arch::from_abi)
+            .collect();
+        assert_eq!(
+            archs,
+            [
+                Some(Arch::AARCH64),
+                Some(Arch::ARM),
+                Some(Arch::ARM),
+                Some(Arch::X86_64),
+                Some(Arch::X86),
+                Some(Arch::RISCV64),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn from_uname_includes_32_bit_code_on_64_bit_kernels() {
+        assert_eq!(Arch::from_uname("aarch64\n"), [Arch::AARCH64, Arch::ARM]);
+        assert_eq!(Arch::from_uname("armv7l"), [Arch::ARM]);
+        assert_eq!(Arch::from_uname("x86_64"), [Arch::X86_64, Arch::X86]);
+        assert_eq!(Arch::from_uname("i686"), [Arch::X86]);
+        assert_eq!(Arch::from_uname("riscv64"), [Arch::RISCV64]);
+        assert!(Arch::from_uname("mips").is_empty());
+    }
+
+    #[test]
+    fn of_file_reads_the_elf_header() {
+        let dir = crate::testutil::TempDir::new();
+        let mut header = vec![0u8; ELF_HEADER_SIZE as usize];
+        header[..SELFMAG].copy_from_slice(ELFMAG);
+        header[EI_CLASS] = ELFCLASS64;
+        header[5] = 1; // little endian
+        header[6] = 1; // ELF version
+        header[18..20].copy_from_slice(&EM_AARCH64.to_le_bytes());
+        let binary = dir.write("libfoo.z.so", &header);
+        let text = dir.write("foo.cfg", b"{}");
+
+        assert_eq!(Arch::of_file(&binary).unwrap(), Some(Arch::AARCH64));
+        assert_eq!(Arch::of_file(&text).unwrap(), None);
+    }
+}
@@ -0,0 +1,34 @@
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Write the build files of `sends` to a tar archive at `path`, each one stored under
+/// its device path so the archive can be extracted at `/`. Entries keep the mode of the
+/// build file and are owned by root, like files sent with `hdc file send`.
+pub fn write_archive(path: &Path, sends: &[(&Path, &Path)], compress: bool) -> io::Result<u64> {
+    let file = File::create(path)?;
+    let file = if compress {
+        append_files(GzEncoder::new(file, Compression::fast()), sends)?.finish()?
+    } else {
+        append_files(file, sends)?
+    };
+    file.sync_all()?;
+    Ok(file.metadata()?.len())
+}
+
+fn append_files<W: Write>(writer: W, sends: &[(&Path, &Path)]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    for (build_file, device_path) in sends {
+        let file = File::open(build_file)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&file.metadata()?);
+        header.set_uid(0);
+        header.set_gid(0);
+        let name = device_path.strip_prefix("/").unwrap_or(device_path);
+        builder.append_data(&mut header, name, file)?;
+    }
+    builder.into_inner()
+}
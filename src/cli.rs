@@ -0,0 +1,311 @@
+use crate::{config::ScanConfig, scan::ScanMode};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "oh-updater")]
+#[command(about = "Push OpenHarmony build files to a device")]
+#[command(version)]
+pub struct BuilderArg {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Print debug logs"
+    )]
+    pub debug: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Format of the file listings and push results"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Show new files that would be pushed, without pushing them")]
+    Status(TargetArgs),
+
+    #[command(about = "Push new files to device")]
+    Push(PushArgs),
+
+    #[command(about = "Show what changed in the build since the last push to a device")]
+    Diff(TargetArgs),
+
+    #[command(about = "Drop or rewrite the record of a device")]
+    Reset(ResetArgs),
+
+    #[command(about = "Restore the device files saved before a push")]
+    Rollback(RollbackArgs),
+
+    #[command(about = "List past pushes or show the files of one of them")]
+    History(HistoryArgs),
+
+    #[command(about = "List attached devices and devices known from the build record")]
+    Devices,
+}
+
+#[derive(Debug, Args)]
+pub struct TargetArgs {
+    #[arg(
+        long,
+        help = "Profile of the config files to take the device, directories and filters from"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        short = 't',
+        long = "connectkey",
+        value_delimiter = ',',
+        help = "Connection key of a target device, the attached device if not given (repeatable or comma separated)"
+    )]
+    pub connect_keys: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "connect_keys",
+        help = "Target every attached device"
+    )]
+    pub all_devices: bool,
+
+    #[arg(
+        short = 'd',
+        long,
+        help = "Directory containing OpenHarmony build files"
+    )]
+    pub build_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Directory containing OpenHarmony build packages, which reflects the directory structure of the device [default: packages/phone]"
+    )]
+    pub build_package_dir: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Rebuild the cached index of the build package directory"
+    )]
+    pub rebuild_index: bool,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct ScanArgs {
+    #[arg(
+        long = "scan-mode",
+        value_enum,
+        help = "Scan the source tree, or only the build package directory and push files to their path in it"
+    )]
+    pub mode: Option<ScanMode>,
+
+    #[arg(
+        long = "scan-root",
+        value_name = "DIR",
+        help = "Directory of the build directory to scan, replacing the configured roots (repeatable)"
+    )]
+    pub roots: Vec<String>,
+
+    #[arg(
+        long = "include",
+        value_name = "GLOB",
+        help = "Only consider files matching this glob, relative to the build directory (repeatable)"
+    )]
+    pub include: Vec<String>,
+
+    #[arg(
+        long = "exclude",
+        value_name = "GLOB",
+        help = "Skip files and directories matching this glob, relative to the build directory (repeatable)"
+    )]
+    pub exclude: Vec<String>,
+}
+
+impl ScanArgs {
+    /// Apply the command line on top of the configured scan settings.
+    pub fn merge_into(&self, config: &mut ScanConfig) {
+        if self.mode.is_some() {
+            config.mode = self.mode;
+        }
+        if !self.roots.is_empty() {
+            config.roots = Some(self.roots.clone());
+        }
+        config.include.extend(self.include.iter().cloned());
+        config.exclude.extend(self.exclude.iter().cloned());
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub options: PushOptions,
+}
+
+#[derive(Debug, Args)]
+pub struct PushOptions {
+    #[arg(
+        short = 'y',
+        long,
+        default_value_t = false,
+        help = "Push without asking for confirmation"
+    )]
+    pub yes: bool,
+
+    #[arg(
+        short = 'f',
+        long = "force",
+        default_value_t = false,
+        help = "force update, ignoring the recorded file hashes"
+    )]
+    pub force_update: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Print the hdc commands instead of running them, leaving the record untouched"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        short = 'j',
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of files sent to a device at the same time"
+    )]
+    pub jobs: u16,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Send the files as one tar archive extracted on the device, falling back to sending them one by one"
+    )]
+    pub batch: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "batch",
+        help = "Compress the batch archive with gzip"
+    )]
+    pub compress: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Compare checksums of the pushed files on the device, sending mismatched files again"
+    )]
+    pub verify: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Don't save the device copies of overwritten files, leaving nothing to roll back"
+    )]
+    pub no_backup: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Send native binaries built for an architecture the device doesn't run, only warning"
+    )]
+    pub allow_abi_mismatch: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Also push the shared libraries the new files need that are missing on the device"
+    )]
+    pub with_deps: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Don't restart services for the pushed files"
+    )]
+    pub no_restart: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Reboot the device after a successful push, also when a restart rule asks for it, and wait for it to come back"
+    )]
+    pub reboot: bool,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 120,
+        help = "How long to wait for a rebooted device and its health check"
+    )]
+    pub boot_timeout: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct ResetArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Record the current build as pushed instead of dropping the record"
+    )]
+    pub rewrite: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    #[arg(help = "Push to roll back, the last push to the device if not given")]
+    pub push_id: Option<String>,
+
+    #[arg(
+        short = 't',
+        long = "connectkey",
+        help = "Connection key of the device, the attached device if not given"
+    )]
+    pub connect_key: Option<String>,
+
+    #[arg(
+        short = 'y',
+        long,
+        default_value_t = false,
+        help = "Roll back without asking for confirmation"
+    )]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    #[arg(help = "Push to show, past pushes are listed if not given")]
+    pub push_id: Option<String>,
+
+    #[arg(
+        short = 't',
+        long = "connectkey",
+        help = "Only list pushes to this device"
+    )]
+    pub connect_key: Option<String>,
+
+    #[arg(short = 'n', long, help = "Only list the last N pushes")]
+    pub limit: Option<usize>,
+}
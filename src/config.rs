@@ -0,0 +1,326 @@
+use crate::{
+    cli::TargetArgs,
+    restart::{default_restart_rules, RestartRule},
+    scan::ScanMode,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+/// Config file in the working directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Project config file looked up in the build directory.
+pub const PROJECT_CONFIG_FILE: &str = ".oh-pusher.toml";
+
+const DEFAULT_BUILD_PACKAGE_DIR: &str = "packages/phone";
+
+/// Settings given at the top level of a config file or in one of its profiles.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub connectkey: Option<String>,
+    pub build_dir: Option<PathBuf>,
+    pub build_package_dir: Option<String>,
+    pub scan: ScanConfig,
+    /// Shell commands run on the device after files were pushed.
+    pub post_push: Option<Vec<String>>,
+    /// Actions taken after pushing matching device paths, replacing the defaults.
+    pub restart: Option<Vec<RestartRule>>,
+    /// Shell command that succeeds once the device is usable again after a reboot.
+    pub health_check: Option<String>,
+}
+
+/// Which build files are considered for pushing.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    pub mode: Option<ScanMode>,
+    /// Directories of the build directory to walk, the default preset if unset.
+    pub roots: Option<Vec<String>>,
+    /// Globs a file must match to be considered, relative to the build directory.
+    pub include: Vec<String>,
+    /// Globs of files and directories to skip, relative to the build directory.
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct ConfigFile {
+    /// Profile used when `--profile` is not given.
+    default_profile: Option<String>,
+    profiles: BTreeMap<String, Settings>,
+    settings: Settings,
+}
+
+/// Everything needed to push one build to one device.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub connect_key: String,
+    pub build_dir: PathBuf,
+    pub build_package_dir: String,
+    pub rebuild_index: bool,
+    pub scan: ScanConfig,
+    pub post_push: Vec<String>,
+    pub restart: Vec<RestartRule>,
+    pub health_check: Option<String>,
+}
+
+impl Settings {
+    /// Overlay `other` on top of these settings.
+    pub fn merge(&mut self, other: Settings) {
+        if other.connectkey.is_some() {
+            self.connectkey = other.connectkey;
+        }
+        if other.build_dir.is_some() {
+            self.build_dir = other.build_dir;
+        }
+        if other.build_package_dir.is_some() {
+            self.build_package_dir = other.build_package_dir;
+        }
+        if other.post_push.is_some() {
+            self.post_push = other.post_push;
+        }
+        if other.restart.is_some() {
+            self.restart = other.restart;
+        }
+        if other.health_check.is_some() {
+            self.health_check = other.health_check;
+        }
+        self.scan.merge(other.scan);
+    }
+
+    /// Merge the config file of the working directory, the project config file of
+    /// the build directory and the command line, in increasing precedence. The
+    /// profile selected by `--profile` or `default_profile` is applied on top of the
+    /// top-level settings of each file.
+    pub fn resolve(workdir: &Path, args: &TargetArgs) -> Result<Self> {
+        let global = ConfigFile::load(&workdir.join(CONFIG_FILE))?;
+
+        let global_profile = args
+            .profile
+            .as_ref()
+            .or(global.default_profile.as_ref())
+            .and_then(|name| global.profiles.get(name));
+        let build_dir = args
+            .build_dir
+            .clone()
+            .or_else(|| global_profile.and_then(|p| p.build_dir.clone()))
+            .or_else(|| global.settings.build_dir.clone());
+
+        let project = match &build_dir {
+            Some(build_dir) => ConfigFile::load(&build_dir.join(PROJECT_CONFIG_FILE))?,
+            None => ConfigFile::default(),
+        };
+
+        let profile = args
+            .profile
+            .clone()
+            .or(global.default_profile)
+            .or(project.default_profile);
+
+        let mut settings = global.settings;
+        let mut global_profiles = global.profiles;
+        let mut project_profiles = project.profiles;
+        let (global_profile, project_profile) = match &profile {
+            Some(name) => {
+                let global_profile = global_profiles.remove(name);
+                let project_profile = project_profiles.remove(name);
+                if global_profile.is_none() && project_profile.is_none() {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("profile {name} is not defined in any config file"),
+                    ));
+                }
+                (global_profile, project_profile)
+            }
+            None => (None, None),
+        };
+        settings.merge(global_profile.unwrap_or_default());
+        settings.merge(project.settings);
+        settings.merge(project_profile.unwrap_or_default());
+
+        // the project config lives in the build directory, it can't move it
+        settings.build_dir = build_dir;
+
+        // several connect keys may be given, the caller picks the devices
+        settings.merge(Settings {
+            connectkey: None,
+            build_dir: None,
+            build_package_dir: args.build_package_dir.clone(),
+            scan: ScanConfig::default(),
+            post_push: None,
+            restart: None,
+            health_check: None,
+        });
+        args.scan.merge_into(&mut settings.scan);
+
+        Ok(settings)
+    }
+
+    /// The target of these settings on the device `connect_key`, the settings must
+    /// name a build directory.
+    pub fn into_target(self, connect_key: String, rebuild_index: bool) -> Result<Target> {
+        let missing = |what: &str| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("no {what} given on the command line or in the selected profile"),
+            )
+        };
+        Ok(Target {
+            connect_key,
+            build_dir: self.build_dir.ok_or_else(|| missing("build directory"))?,
+            build_package_dir: self
+                .build_package_dir
+                .unwrap_or_else(|| String::from(DEFAULT_BUILD_PACKAGE_DIR)),
+            rebuild_index,
+            scan: self.scan,
+            post_push: self.post_push.unwrap_or_default(),
+            restart: self.restart.unwrap_or_else(default_restart_rules),
+            health_check: self.health_check,
+        })
+    }
+}
+
+impl ScanConfig {
+    /// Overlay `other`: its mode and roots replace these, its globs are added.
+    pub fn merge(&mut self, other: ScanConfig) {
+        if other.mode.is_some() {
+            self.mode = other.mode;
+        }
+        if other.roots.is_some() {
+            self.roots = other.roots;
+        }
+        self.include.extend(other.include);
+        self.exclude.extend(other.exclude);
+    }
+}
+
+impl ConfigFile {
+    /// Read the config file at `path`, the defaults if there is none.
+    fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content).map_err(|error| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: {error}", path.display()),
+                )
+            }),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(ConfigFile::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The keys left after taking out the profiles are the top-level settings, so
+    /// unknown keys are rejected there as in the profiles.
+    fn parse(content: &str) -> std::result::Result<Self, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(content)?;
+        let default_profile = table
+            .remove("default_profile")
+            .map(toml::Value::try_into)
+            .transpose()?;
+        let profiles = table
+            .remove("profiles")
+            .map(toml::Value::try_into)
+            .transpose()?
+            .unwrap_or_default();
+        Ok(ConfigFile {
+            default_profile,
+            profiles,
+            settings: toml::Value::Table(table).try_into()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        target: TargetArgs,
+    }
+
+    fn resolve(workdir: &TempDir, args: &[&str]) -> Result<Settings> {
+        let args = Cli::parse_from(std::iter::once("status").chain(args.iter().copied()));
+        Settings::resolve(workdir.path(), &args.target)
+    }
+
+    #[test]
+    fn later_sources_take_precedence() {
+        let workdir = TempDir::new();
+        let build = TempDir::new();
+        workdir.write(
+            CONFIG_FILE,
+            format!(
+                r#"
+                connectkey = "global"
+                build_dir = "{}"
+                build_package_dir = "global"
+                post_push = ["global"]
+                health_check = "global"
+
+                [profiles.rk]
+                connectkey = "global-profile"
+                build_package_dir = "global-profile"
+                post_push = ["global-profile"]
+                "#,
+                build.path().display()
+            )
+            .as_bytes(),
+        );
+        build.write(
+            PROJECT_CONFIG_FILE,
+            br#"
+            build_package_dir = "project"
+            post_push = ["project"]
+
+            [profiles.rk]
+            post_push = ["project-profile"]
+            "#,
+        );
+
+        let settings = resolve(&workdir, &[]).unwrap();
+        assert_eq!(settings.connectkey.as_deref(), Some("global"));
+        assert_eq!(settings.build_package_dir.as_deref(), Some("project"));
+        assert_eq!(settings.post_push.unwrap(), ["project"]);
+
+        let settings = resolve(&workdir, &["--profile", "rk"]).unwrap();
+        assert_eq!(settings.connectkey.as_deref(), Some("global-profile"));
+        assert_eq!(settings.build_dir.as_deref(), Some(build.path()));
+        assert_eq!(settings.build_package_dir.as_deref(), Some("project"));
+        assert_eq!(settings.post_push.unwrap(), ["project-profile"]);
+        assert_eq!(settings.health_check.as_deref(), Some("global"));
+
+        let settings =
+            resolve(&workdir, &["--profile", "rk", "--build-package-dir", "cli"]).unwrap();
+        assert_eq!(settings.build_package_dir.as_deref(), Some("cli"));
+
+        assert_eq!(
+            resolve(&workdir, &["--profile", "missing"])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for content in [
+            "conectkey = \"x\"",
+            "[profiles.rk]\nconectkey = \"x\"",
+            "[scan]\nroot = [\"out\"]",
+        ] {
+            let workdir = TempDir::new();
+            workdir.write(CONFIG_FILE, content.as_bytes());
+            let error = resolve(&workdir, &[]).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{content}");
+        }
+    }
+}
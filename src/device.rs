@@ -0,0 +1,47 @@
+use crate::transport::DeviceTransport;
+use log::info;
+use std::io::{self, Error, ErrorKind, Result, Write};
+
+/// Pick the device to use from `hdc list targets`: the only one attached, or the
+/// one the user chooses when there are several.
+pub fn discover_device(transport: &dyn DeviceTransport) -> Result<String> {
+    let mut targets = transport.list_targets()?;
+    match targets.len() {
+        0 => Err(Error::new(
+            ErrorKind::NotFound,
+            "no connect key given and no device attached, check `hdc list targets`",
+        )),
+        1 => {
+            let target = targets.remove(0);
+            info!("Using the only attached device {target}");
+            Ok(target)
+        }
+        _ => choose_device(targets),
+    }
+}
+
+fn choose_device(mut targets: Vec<String>) -> Result<String> {
+    eprintln!("Several devices are attached:");
+    for (i, target) in targets.iter().enumerate() {
+        eprintln!("  {}) {target}", i + 1);
+    }
+
+    loop {
+        eprint!("Which device do you want to use? [1-{}] ", targets.len());
+        let _ = io::stderr().flush();
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "no device chosen, pass one with -t",
+            ));
+        }
+        match input.trim().parse::<usize>() {
+            Ok(choice) if (1..=targets.len()).contains(&choice) => {
+                return Ok(targets.swap_remove(choice - 1))
+            }
+            _ => eprintln!("Please enter a number between 1 and {}", targets.len()),
+        }
+    }
+}
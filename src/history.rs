@@ -0,0 +1,96 @@
+use crate::record::RecordScope;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+};
+
+/// Push sessions of the working directory, one JSON object per line.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// One build file written to one device path in a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryFile {
+    pub build_file: PathBuf,
+    pub device_path: PathBuf,
+    /// Content hash of the build file, as in the record.
+    pub hash: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A push to one device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushSession {
+    /// Also names the snapshot of the files the push overwrote.
+    pub push_id: String,
+    pub connectkey: String,
+    pub build_dir: PathBuf,
+    pub build_package_dir: String,
+    pub started: String,
+    pub finished: String,
+    /// Whether the overwritten device files were saved for a rollback.
+    pub snapshot: bool,
+    pub files: Vec<HistoryFile>,
+}
+
+impl PushSession {
+    pub fn new(push_id: &str, scope: &RecordScope, started: String) -> Self {
+        PushSession {
+            push_id: push_id.to_owned(),
+            connectkey: scope.connectkey.clone(),
+            build_dir: scope.build_dir.clone(),
+            build_package_dir: scope.build_package_dir.clone(),
+            started,
+            finished: String::new(),
+            snapshot: false,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.error.is_none())
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.files.len() - self.succeeded()
+    }
+}
+
+/// Add `session` to the end of the history.
+pub fn append(workdir: &Path, session: &PushSession) -> Result<()> {
+    let mut line = serde_json::to_string(session)?;
+    line.push('\n');
+    // a single write keeps the line whole in append mode
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(workdir.join(HISTORY_FILE))?
+        .write_all(line.as_bytes())
+}
+
+/// Every recorded session, oldest first. Lines that don't parse are skipped.
+pub fn load(workdir: &Path) -> Result<Vec<PushSession>> {
+    let path = workdir.join(HISTORY_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(number, line)| {
+            serde_json::from_str(line)
+                .inspect_err(|error| {
+                    warn!("skip line {} of {}: {error}", number + 1, path.display())
+                })
+                .ok()
+        })
+        .collect())
+}
@@ -0,0 +1,161 @@
+use crate::workdir::write_atomic;
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+const INDEX_DIR: &str = "package_index";
+
+/// File name to device paths lookup table of a build package directory.
+///
+/// The table is built in one walk over the package directory and cached in the
+/// working directory until the mtime of any directory in it changes, which adding,
+/// removing or renaming a file does.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PackageIndex {
+    package_dir: PathBuf,
+    modified: String,
+    entries: HashMap<String, Vec<PathBuf>>,
+}
+
+impl PackageIndex {
+    /// Walk `package_dir` once and index every file by its name.
+    pub fn build(package_dir: &Path) -> Result<Self> {
+        let mut entries: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for entry in WalkDir::new(package_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let relative = entry
+                .path()
+                .strip_prefix(package_dir)
+                .expect("strip build package directory error");
+            entries
+                .entry(entry.file_name().to_string_lossy().into_owned())
+                .or_default()
+                .push(Path::new("/").join(relative));
+        }
+        entries.values_mut().for_each(|paths| paths.sort());
+
+        Ok(PackageIndex {
+            package_dir: package_dir.to_path_buf(),
+            modified: modified_date(package_dir)?,
+            entries,
+        })
+    }
+
+    /// Load the cached index of `package_dir` from `workdir`, rebuilding and
+    /// caching it when missing, stale or `rebuild` is set.
+    pub fn load_or_build(package_dir: &Path, workdir: &Path, rebuild: bool) -> Result<Self> {
+        let cache_file = Self::cache_file(package_dir, workdir);
+
+        if !rebuild && package_dir.exists() {
+            let modified = modified_date(package_dir)?;
+            let cached = fs::read(&cache_file)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<PackageIndex>(&bytes).ok())
+                .filter(|index| index.package_dir == package_dir && index.modified == modified);
+            if let Some(index) = cached {
+                debug!("use cached package index {}", cache_file.display());
+                return Ok(index);
+            }
+        }
+
+        debug!("build package index of {}", package_dir.display());
+        let index = Self::build(package_dir)?;
+        fs::create_dir_all(cache_file.parent().unwrap())?;
+        write_atomic(&cache_file, &serde_json::to_vec(&index)?)?;
+        Ok(index)
+    }
+
+    /// Device paths of every packaged file named `file_name`.
+    pub fn lookup(&self, file_name: &OsStr) -> &[PathBuf] {
+        self.entries
+            .get(file_name.to_string_lossy().as_ref())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn cache_file(package_dir: &Path, workdir: &Path) -> PathBuf {
+        let key = blake3::hash(package_dir.as_os_str().as_encoded_bytes()).to_hex();
+        workdir
+            .join(INDEX_DIR)
+            .join(format!("{}.json", &key.as_str()[..16]))
+    }
+}
+
+/// Newest mtime of `path` and the directories below it, only directories are stat'ed.
+fn modified_date(path: &Path) -> Result<String> {
+    let mut newest = None;
+    for entry in WalkDir::new(path)
+        .into_iter()
+        .filter_entry(|e| e.file_type().is_dir())
+    {
+        let modified = match entry {
+            Ok(entry) => entry.metadata().map_err(Error::from)?.modified()?,
+            Err(e)
+                if e.io_error()
+                    .is_some_and(|e| e.kind() == ErrorKind::NotFound) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        newest = newest.max(Some(modified));
+    }
+    Ok(newest
+        .map(|time| DateTime::<Utc>::from(time).to_rfc3339())
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    #[test]
+    fn cache_is_rebuilt_when_a_nested_directory_changes() {
+        let build = TempDir::new();
+        let workdir = TempDir::new();
+        let package_dir = build.path().join("packages/phone");
+        build.write("packages/phone/system/lib64/libfoo.z.so", b"");
+
+        let index = PackageIndex::load_or_build(&package_dir, workdir.path(), false).unwrap();
+        assert_eq!(
+            index.lookup(OsStr::new("libfoo.z.so")),
+            [PathBuf::from("/system/lib64/libfoo.z.so")]
+        );
+
+        // only the mtime of lib64 changes, timestamps may be too coarse to tell otherwise
+        let lib64 = build.write("packages/phone/system/lib64/libnew.z.so", b"");
+        File::open(lib64.parent().unwrap())
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+
+        let index = PackageIndex::load_or_build(&package_dir, workdir.path(), false).unwrap();
+        assert_eq!(
+            index.lookup(OsStr::new("libnew.z.so")),
+            [PathBuf::from("/system/lib64/libnew.z.so")]
+        );
+    }
+}
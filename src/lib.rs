@@ -0,0 +1,20 @@
+pub mod abi;
+pub mod archive;
+pub mod cli;
+pub mod config;
+pub mod device;
+pub mod history;
+pub mod index;
+pub mod manifest;
+pub mod mounts;
+pub mod pusher;
+pub mod record;
+pub mod report;
+pub mod restart;
+pub mod scan;
+pub mod snapshot;
+pub mod transport;
+pub mod workdir;
+
+#[cfg(test)]
+mod testutil;
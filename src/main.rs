@@ -1,65 +1,27 @@
 use chrono::DateTime;
 use clap::Parser;
-use log::{debug, info};
-use serde::{Deserialize, Serialize};
+use log::{debug, error, info, warn};
+use oh_buildfile_pusher_rs::{
+    cli::{BuilderArg, Command, HistoryArgs, OutputFormat, ResetArgs, RollbackArgs, TargetArgs},
+    config::Settings,
+    device::discover_device,
+    history::{self, PushSession},
+    mounts::remount_writable,
+    pusher::{decide_send_by_user, diff_devices, push_devices, status_devices, BuildFilePusher},
+    record::Records,
+    report::PushReport,
+    snapshot::Snapshot,
+    transport::{shell_quote, DeviceTransport, DryRunTransport, HdcTransport},
+    workdir::{establish_workdir, WorkdirLock},
+};
 use std::{
-    collections::HashMap,
-    env,
-    ffi::OsStr,
-    fs::{self, File},
-    io::{self, Result, Write},
-    path::PathBuf,
-    process::Command,
+    io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, Mutex},
 };
-use walkdir::WalkDir;
-
-#[derive(Debug, Parser)]
-#[command(name = "oh-updater")]
-#[command(about = "Push OpenHarmony build files to a device")]
-#[command(version)]
-struct BuilderArg {
-    #[arg(
-        short = 't',
-        long = "connectkey",
-        help = "Connection key of the target device"
-    )]
-    connect_key: String,
-
-    #[arg(
-        short = 'd',
-        long,
-        help = "Directory containing OpenHarmony build files"
-    )]
-    build_dir: PathBuf,
-
-    #[arg(
-        long,
-        default_value_t = String::from("packages/phone"),
-        help = "Directory containing OpenHarmony build packages, which reflects the directory structure of the devic"
-    )]
-    build_package_dir: String,
-
-    #[arg(
-        short = 'p',
-        long,
-        default_value_t = false,
-        help = "Push new files to device"
-    )]
-    push: bool,
-
-    #[arg(long, default_value_t = false, help = "Print debug logs")]
-    debug: bool,
 
-    #[arg(
-        short = 'f',
-        long = "force",
-        default_value_t = false,
-        help = "force update"
-    )]
-    force_update: bool,
-}
-
-fn main() {
+fn main() -> ExitCode {
     // Initialize clap command
     let args = BuilderArg::parse();
 
@@ -73,327 +35,436 @@ fn main() {
         .try_init()
         .unwrap();
 
-    // Display the values
     debug!("oh builder pusher");
-    debug!("Device ID: {}", args.connect_key);
-    debug!("Build Directory: {}", args.build_dir.display());
+    let workdir = establish_workdir().unwrap();
 
     // main logic
-    BuildFilePusher {
-        args,
-        workdir: establish_workdir().unwrap(),
-        records: None,
+    match args.command {
+        Command::Status(target) => {
+            match pushers(&target, &workdir, Arc::new(HdcTransport::default())) {
+                Ok((_lock, pushers)) => status_devices(&pushers, args.output),
+                Err(code) => code,
+            }
+        }
+        Command::Push(push) => {
+            let transport: Arc<dyn DeviceTransport> = if push.options.dry_run {
+                Arc::new(DryRunTransport {
+                    to_stderr: args.output == OutputFormat::Json,
+                })
+            } else {
+                Arc::new(HdcTransport::default())
+            };
+            match pushers(&push.target, &workdir, transport) {
+                Ok((_lock, pushers)) => push_devices(&pushers, &push.options, args.output),
+                Err(code) => code,
+            }
+        }
+        Command::Diff(target) => {
+            match pushers(&target, &workdir, Arc::new(HdcTransport::default())) {
+                Ok((_lock, pushers)) => diff_devices(&pushers),
+                Err(code) => code,
+            }
+        }
+        Command::Reset(reset_args) => reset(reset_args, &workdir),
+        Command::Rollback(rollback_args) => rollback(rollback_args, args.output, &workdir),
+        Command::History(history_args) => history(history_args, args.output, &workdir),
+        Command::Devices => devices(&workdir),
     }
-    .run()
 }
 
-const RECORD_FILE: &str = "build_record.json";
-
-const DIRS_TO_SCAN: [&str; 21] = [
-    "applications",
-    "arkcompiler",
-    "base",
-    "build",
-    "commonlibrary",
-    "cpp",
-    "developtools",
-    "device",
-    "domains",
-    "drivers",
-    "foundation",
-    "isa",
-    "kernel",
-    "libpandabase",
-    "out",
-    "test",
-    "third_party",
-    "vendor",
-    "communication",
-    "multimedia",
-    "distributedhardware",
-];
+/// Settings of `args` merged with the config files, logging the error if they are invalid.
+fn settings(args: &TargetArgs, workdir: &Path) -> Result<Settings, ExitCode> {
+    Settings::resolve(workdir, args).map_err(|error| {
+        error!("{error}");
+        ExitCode::FAILURE
+    })
+}
 
-fn establish_workdir() -> Result<PathBuf> {
-    let xdg_conf_home = env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
-        let home = env::var("HOME").expect("HOME not set");
-        format!("{home}/.config")
-    });
-    let workdir = PathBuf::from(xdg_conf_home).join("hdc_push_buildfiles");
-    fs::create_dir_all(&workdir)?;
-    Ok(workdir)
+/// The record of the working directory, logging the error if it can't be used.
+fn records(workdir: &Path) -> Result<Records, ExitCode> {
+    Records::load(workdir).map_err(|error| {
+        error!("{error}");
+        ExitCode::FAILURE
+    })
 }
 
-#[derive(Serialize, Deserialize)]
-struct Record {
-    connectkey: String,
-    last_modified_date: String,
+/// Devices targeted by `args`: the connect keys given on the command line, every
+/// attached device, the configured connect key, or else the attached device.
+fn connect_keys(args: &TargetArgs, settings: &Settings) -> Result<Vec<String>, ExitCode> {
+    // devices are looked up even for dry runs, listing targets changes nothing
+    let keys = if !args.connect_keys.is_empty() {
+        Ok(args.connect_keys.clone())
+    } else if args.all_devices {
+        HdcTransport::default().list_targets().and_then(|targets| {
+            if targets.is_empty() {
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no device attached, check `hdc list targets`",
+                ))
+            } else {
+                Ok(targets)
+            }
+        })
+    } else if let Some(connect_key) = &settings.connectkey {
+        Ok(vec![connect_key.clone()])
+    } else {
+        discover_device(&HdcTransport::default()).map(|connect_key| vec![connect_key])
+    };
+    keys.map_err(|error| {
+        error!("{error}");
+        ExitCode::FAILURE
+    })
 }
 
-struct BuildFilePusher {
-    args: BuilderArg,
-    workdir: PathBuf,
-    records: Option<Vec<Record>>,
+/// One pusher per targeted device, sharing the record under the working directory lock.
+fn pushers(
+    args: &TargetArgs,
+    workdir: &Path,
+    transport: Arc<dyn DeviceTransport>,
+) -> Result<(WorkdirLock, Vec<BuildFilePusher>), ExitCode> {
+    let settings = settings(args, workdir)?;
+    let mut targets = Vec::new();
+    for connect_key in connect_keys(args, &settings)? {
+        let target = settings
+            .clone()
+            .into_target(connect_key, args.rebuild_index)
+            .map_err(|error| {
+                error!("{error}");
+                ExitCode::FAILURE
+            })?;
+
+        // Display the values
+        debug!("Device ID: {}", target.connect_key);
+        debug!("Build Directory: {}", target.build_dir.display());
+        targets.push(target);
+    }
+
+    // hold the working directory until this run is done with the record
+    let lock = WorkdirLock::acquire(workdir).expect("lock working directory");
+    let records = Arc::new(Mutex::new(records(workdir)?));
+
+    let pushers = targets
+        .into_iter()
+        .map(|target| {
+            BuildFilePusher::new(
+                target,
+                workdir.to_path_buf(),
+                Arc::clone(&records),
+                Arc::clone(&transport),
+            )
+        })
+        .collect();
+    Ok((lock, pushers))
 }
 
-impl BuildFilePusher {
-    fn read_records(&mut self) {
-        let record_file = self.workdir.join(RECORD_FILE);
-        if record_file.exists() {
-            self.records = Some(
-                serde_json::from_slice::<Vec<Record>>(
-                    &std::fs::read(record_file).expect("record file corrupted"),
-                )
-                .expect("json format corrupted"),
-            );
-            for record in self.records.as_deref().unwrap() {
-                debug!(
-                    "connectkey: {}, last_modified_date: {}",
-                    record.connectkey, record.last_modified_date
-                )
+fn reset(args: ResetArgs, workdir: &Path) -> ExitCode {
+    if args.rewrite {
+        return match pushers(&args.target, workdir, Arc::new(HdcTransport::default())) {
+            Ok((_lock, pushers)) => {
+                for pusher in &pushers {
+                    pusher.rewrite_record();
+                }
+                ExitCode::SUCCESS
             }
-        }
+            Err(code) => code,
+        };
     }
 
-    fn record_entry_exists(&self, connect_key: &str) -> bool {
-        match &self.records {
-            Some(records) => records.iter().any(|x| x.connectkey == connect_key),
-            None => false,
+    let settings = match settings(&args.target, workdir) {
+        Ok(settings) => settings,
+        Err(code) => return code,
+    };
+    let connect_keys = match connect_keys(&args.target, &settings) {
+        Ok(connect_keys) => connect_keys,
+        Err(code) => return code,
+    };
+
+    let _lock = WorkdirLock::acquire(workdir).expect("lock working directory");
+    let mut records = match records(workdir) {
+        Ok(records) => records,
+        Err(code) => return code,
+    };
+    let mut changed = false;
+    for connect_key in &connect_keys {
+        let dropped = records.remove(connect_key, settings.build_dir.as_deref());
+        if dropped > 0 {
+            changed = true;
+            info!("Dropped {dropped} records of device {connect_key}");
+        } else {
+            info!("No record for device {connect_key}");
         }
     }
-
-    fn record_entry(&self, connect_key: &str) -> Option<&Record> {
-        match &self.records {
-            Some(records) => records.iter().find(|x| x.connectkey == connect_key),
-            None => None,
-        }
+    if changed {
+        records.save().expect("write json to record file");
     }
+    ExitCode::SUCCESS
+}
 
-    fn record_entry_mut(&mut self, connect_key: &str) -> Option<&mut Record> {
-        match &mut self.records {
-            Some(records) => records.iter_mut().find(|x| x.connectkey == connect_key),
-            None => None,
+/// Put back the device files saved before a push and drop the restored files from
+/// the record, so the next push sends them again.
+fn rollback(args: RollbackArgs, output: OutputFormat, workdir: &Path) -> ExitCode {
+    let transport = HdcTransport::default();
+    let _lock = WorkdirLock::acquire(workdir).expect("lock working directory");
+    let mut records = match records(workdir) {
+        Ok(records) => records,
+        Err(code) => return code,
+    };
+
+    let snapshot = match &args.push_id {
+        Some(push_id) => Snapshot::load(workdir, push_id),
+        None => args
+            .connect_key
+            .clone()
+            .map_or_else(|| discover_device(&transport), Ok)
+            .and_then(|connect_key| {
+                Snapshot::list(workdir)
+                    .into_iter()
+                    .rev()
+                    .find(|snapshot| snapshot.connectkey == connect_key)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no snapshot of device {connect_key}"),
+                        )
+                    })
+            }),
+    };
+    let snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            error!("{error}");
+            return ExitCode::FAILURE;
         }
+    };
+    if args
+        .connect_key
+        .as_ref()
+        .is_some_and(|connect_key| *connect_key != snapshot.connectkey)
+    {
+        error!(
+            "push {} was to device {}, not {}",
+            snapshot.push_id,
+            snapshot.connectkey,
+            args.connect_key.unwrap_or_default()
+        );
+        return ExitCode::FAILURE;
     }
 
-    pub fn run(&mut self) {
-        debug!("Working directory: {}", self.workdir.as_path().display());
-
-        // read or init the build record
-        self.read_records();
-
-        let latest_modified_date = match self.record_entry(&self.args.connect_key) {
-            None => DateTime::<chrono::Utc>::MIN_UTC,
-            Some(Record {
-                connectkey: _,
-                last_modified_date,
-            }) => DateTime::parse_from_rfc3339(last_modified_date)
-                .expect("iso time format error")
-                .into(),
+    info!(
+        "Rolling back push {} to device {}:",
+        snapshot.push_id, snapshot.connectkey
+    );
+    for entry in &snapshot.files {
+        let action = match (entry.existed, entry.saved) {
+            (true, true) => "restore",
+            (false, _) => "remove",
+            (true, false) => "keep, not backed up",
         };
+        println!("{}\t{action}", entry.device_path.display());
+    }
+    if !args.yes && !decide_send_by_user(output) {
+        return ExitCode::SUCCESS;
+    }
 
-        // scan directories and update lastest_modified_date
-        let all_files: Vec<_> = DIRS_TO_SCAN
-            .into_iter()
-            .map(|path| self.args.build_dir.join(path))
-            .filter(|path| path.exists())
-            .flat_map(|path| self.get_files(path))
-            .collect();
-
-        debug!("len of all files: {}", all_files.len());
-
-        // filter the new files by lastest_modified_date
-
-        let new_files: Vec<_> = all_files
-            .into_iter()
-            .filter(|f| {
-                let file_date = DateTime::<chrono::Utc>::from(
-                    f.metadata()
-                        .expect("open candidate file fail")
-                        .modified()
-                        .expect("get candidate file's modified fail"),
-                );
-                if self.args.force_update {
-                    file_date >= latest_modified_date
-                } else {
-                    file_date > latest_modified_date
-                }
-            })
-            .collect();
-
-        debug!("len of new files: {}", new_files.len());
-
-        // store newer timestamp
-        let new_modified_dates: Vec<_> = new_files
-            .iter()
-            .map(|f| {
-                DateTime::<chrono::Utc>::from(
-                    f.metadata()
-                        .expect("open candidate file fail")
-                        .modified()
-                        .expect("get candidate file's modified fail"),
-                )
-            })
-            .collect();
-
-        debug!("len of new_modified_dates: {}", new_modified_dates.len());
-
-        // if no newer date, this variable won't be used
-        let new_modified_date = new_modified_dates.into_iter().max().unwrap_or_default();
-
-        // map files to device path
-        // TODO: detect unmapped file
-        // FIXME: logic error here
-        // let build_file_map: HashMap<_, _> = new_files
-        //     .into_iter()
-        //     .flat_map(|f| self.find_device_path(f))
-        //     .collect();
-
-        let build_file_map: HashMap<_, _> = new_files
-            .into_iter()
-            .map(|f| {
-                (
-                    f.clone(),
-                    self.find_device_path(f.file_name().expect("get build file name")),
-                )
-            })
-            .collect();
+    let connect_key = &snapshot.connectkey;
+    let device_paths: Vec<_> = snapshot
+        .files
+        .iter()
+        .filter(|entry| entry.saved || !entry.existed)
+        .map(|entry| entry.device_path.as_path())
+        .collect();
+    let read_only = remount_writable(&transport, connect_key, &device_paths);
+
+    let mut report = PushReport::default();
+    let mut rolled_back = Vec::new();
+    for entry in &snapshot.files {
+        // a removal has no local file
+        let local = match (entry.existed, entry.saved) {
+            (true, true) => Snapshot::file_path(workdir, &snapshot.push_id, &entry.device_path),
+            (false, _) => PathBuf::new(),
+            (true, false) => continue,
+        };
+        if let Some(error) = read_only.get(entry.device_path.as_path()) {
+            report.record_error(&local, &entry.device_path, error.clone());
+            continue;
+        }
+        let outcome = if entry.existed {
+            transport.send_file(connect_key, &local, &entry.device_path)
+        } else {
+            let command = format!(
+                "rm -f {}",
+                shell_quote(&entry.device_path.to_string_lossy())
+            );
+            transport.shell_checked(connect_key, &command)
+        };
+        report.record(&local, &entry.device_path, outcome);
+        if report
+            .results
+            .last()
+            .is_some_and(|result| result.succeeded())
+        {
+            rolled_back.extend(entry.build_files.iter());
+        }
+    }
+    report.print_summary();
 
-        debug!("len of build file map: {}", build_file_map.len());
+    if let Some(mut record) = records.get(&snapshot.scope()).cloned() {
+        for build_file in rolled_back {
+            record.files.restore(build_file, None);
+        }
+        records.upsert(record);
+        records.save().expect("write json to record file");
+    }
 
-        // decide whether to send files
-        let mut send = false;
-        if !build_file_map.is_empty() && self.record_entry_exists(&self.args.connect_key) {
-            info!("Found the following new files: ");
-            for (build_file, device_path) in &build_file_map {
-                println!("{} -> {}", build_file.display(), device_path.display());
-            }
-            send = self.args.push || self.decide_send_by_user();
-            if send {
-                Command::new("hdc")
-                    .args([
-                        "-t",
-                        &self.args.connect_key,
-                        "shell",
-                        "mount",
-                        "-o",
-                        "remount,rw",
-                        "/",
-                    ])
-                    .status()
-                    .expect("fail to mount directory to device");
+    if report.failed() > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
 
-                build_file_map
-                    .into_iter()
-                    .for_each(|(build_file, device_path)| {
-                        Command::new("hdc")
-                            .args([
-                                "-t",
-                                &self.args.connect_key,
-                                "file",
-                                "send",
-                                build_file.to_str().unwrap(),
-                                device_path.to_str().unwrap(),
-                            ])
-                            .status()
-                            .unwrap_or_else(|error| {
-                                panic!(
-                                    "fail to send {} to {}, error: {error}",
-                                    build_file.display(),
-                                    device_path.display()
-                                )
-                            });
-                    });
-            }
+/// List the recorded pushes, or the files of the push `args.push_id`.
+fn history(args: HistoryArgs, output: OutputFormat, workdir: &Path) -> ExitCode {
+    let sessions = match history::load(workdir) {
+        Ok(sessions) => sessions,
+        Err(error) => {
+            error!("fail to read the history: {error}");
+            return ExitCode::FAILURE;
         }
+    };
 
-        // modified records
-        if send || !self.record_entry_exists(&self.args.connect_key) {
-            if self.record_entry_exists(&self.args.connect_key) {
-                self.record_entry_mut(&self.args.connect_key.clone())
-                    .unwrap()
-                    .last_modified_date = new_modified_date.to_rfc3339();
-            } else {
-                let records = &mut self.records;
-                match records {
-                    Some(ref mut r) => r.push(Record {
-                        connectkey: self.args.connect_key.clone(),
-                        last_modified_date: new_modified_date.to_rfc3339(),
-                    }),
-                    None => {
-                        *records = Some(vec![Record {
-                            connectkey: self.args.connect_key.clone(),
-                            last_modified_date: new_modified_date.to_rfc3339(),
-                        }])
-                    }
-                }
+    if let Some(push_id) = &args.push_id {
+        let Some(session) = sessions.iter().find(|session| &session.push_id == push_id) else {
+            error!("no push {push_id} in the history");
+            return ExitCode::FAILURE;
+        };
+        if output == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(session).expect("convert history to json")
+            );
+            return ExitCode::SUCCESS;
+        }
+        println!("push:     {}", session.push_id);
+        println!("device:   {}", session.connectkey);
+        println!(
+            "build:    {}",
+            session.build_dir.join(&session.build_package_dir).display()
+        );
+        println!("started:  {}", local_date(&session.started));
+        println!("finished: {}", local_date(&session.finished));
+        println!("snapshot: {}", if session.snapshot { "yes" } else { "no" });
+        println!(
+            "files:    {} succeeded, {} failed",
+            session.succeeded(),
+            session.failed()
+        );
+        for file in &session.files {
+            let hash = file.hash.as_deref().unwrap_or("-");
+            match &file.error {
+                None => println!(
+                    "  OK    {} -> {}  {hash}",
+                    file.build_file.display(),
+                    file.device_path.display()
+                ),
+                Some(error) => println!(
+                    "  FAIL  {} -> {}  {hash}: {error}",
+                    file.build_file.display(),
+                    file.device_path.display()
+                ),
             }
-
-            // update record file
-            let records = serde_json::to_string(&self.records).expect("convert records to json");
-
-            let mut record_file = File::create(self.workdir.join(RECORD_FILE))
-                .expect("open record file in write-only modee");
-
-            record_file
-                .write_all(records.as_bytes())
-                .expect("write json to record file");
-
-            info!("update record files");
         }
-
-        info!("Unchange");
-
-        // TODO: print helper logs
-        // if new_files.is_empty() && self.record_entry_exists(&self.args.connect_key) {
-        //     info!("No new files since last check.");
-        // } else if build_file_map.is_empty() && self.record_entry_exists(&self.args.connect_key) {
-        //     info!("No pushable new files since last check.");
-        // } else if !self.record_entry_exists(&self.args.connect_key) {
-        //     info!(
-        //         "First time running this script for device {}. Last modified date is {}.",
-        //         self.args.connect_key, new_modified_date
-        //     );
-        // }
+        return ExitCode::SUCCESS;
     }
 
-    fn get_files(&self, path: PathBuf) -> Vec<PathBuf> {
-        WalkDir::new(path)
-            .into_iter()
-            .filter(|f| f.as_ref().is_ok_and(|entry| entry.file_type().is_file()))
-            .map(|f| f.unwrap().into_path())
-            .collect()
+    let sessions: Vec<&PushSession> = sessions
+        .iter()
+        .filter(|session| {
+            args.connect_key
+                .as_ref()
+                .is_none_or(|connect_key| *connect_key == session.connectkey)
+        })
+        .collect();
+    let skip = args
+        .limit
+        .map_or(0, |limit| sessions.len().saturating_sub(limit));
+    let sessions = &sessions[skip..];
+    if output == OutputFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(sessions).expect("convert history to json")
+        );
+        return ExitCode::SUCCESS;
     }
-
-    fn find_device_path(&self, file_name: &OsStr) -> PathBuf {
-        // debug!("file_name={:?}", file_name);
-        let package_dir = self.args.build_dir.join(&self.args.build_package_dir);
-        WalkDir::new(package_dir.as_path())
-            .into_iter()
-            .filter(|f| {
-                f.as_ref()
-                    .is_ok_and(|e| e.file_type().is_file() && e.file_name() == file_name)
-            })
-            .map(|f| {
-                f.expect("walk device path file error")
-                    .path()
-                    .strip_prefix(package_dir.as_path())
-                    .expect("strip build package directory error")
-                    .to_path_buf()
-            })
-            .collect()
+    if sessions.is_empty() {
+        info!("No pushes in the history");
     }
+    for session in sessions {
+        println!(
+            "{}\t{}\t{}\t{}\t{} succeeded, {} failed",
+            session.push_id,
+            local_date(&session.started),
+            session.connectkey,
+            session.build_dir.join(&session.build_package_dir).display(),
+            session.succeeded(),
+            session.failed()
+        );
+    }
+    ExitCode::SUCCESS
+}
 
-    fn decide_send_by_user(&self) -> bool {
-        print!("Do you want to proceed? [Y/n] ");
-        let _ = io::stdout().flush();
-
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
+/// A date stored as RFC 3339, formatted for display.
+fn local_date(date: &str) -> String {
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S %:z").to_string())
+        .unwrap_or_else(|_| date.to_owned())
+}
 
-        let input = input.trim();
+/// List attached devices and devices known from the record.
+fn devices(workdir: &Path) -> ExitCode {
+    let attached = HdcTransport::default()
+        .list_targets()
+        .unwrap_or_else(|error| {
+            warn!("fail to list attached devices: {error}");
+            Vec::new()
+        });
+    let records = match records(workdir) {
+        Ok(records) => records,
+        Err(code) => return code,
+    };
+    if attached.is_empty() && records.iter().next().is_none() {
+        info!("No known devices");
+    }
 
-        matches!(input, "y" | "Y" | "")
+    let state = |connect_key: &str| {
+        if attached.iter().any(|target| target == connect_key) {
+            "attached"
+        } else {
+            "offline"
+        }
+    };
+    for record in records.iter() {
+        let last_push = record
+            .last_push
+            .as_deref()
+            .map_or_else(|| String::from("never"), local_date);
+        let build = match (&record.build_dir, &record.build_package_dir) {
+            (Some(build_dir), Some(package_dir)) => {
+                build_dir.join(package_dir).display().to_string()
+            }
+            _ => String::from("(any build)"),
+        };
+        println!(
+            "{}\t{}\t{build}\tlast push: {last_push}\tfiles: {}",
+            record.connectkey,
+            state(&record.connectkey),
+            record.files.len()
+        );
+    }
+    for target in &attached {
+        if !records.iter().any(|record| &record.connectkey == target) {
+            println!("{target}\tattached\t(no record)");
+        }
     }
+    ExitCode::SUCCESS
 }
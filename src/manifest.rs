@@ -0,0 +1,197 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs::File, io::Result, path::Path};
+
+/// Size and content hash of a file at the time it was last pushed.
+///
+/// `modified` is only a hint: when size and mtime still match, the stored hash
+/// is reused instead of re-reading the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    pub modified: String,
+    pub hash: String,
+}
+
+impl FileEntry {
+    pub fn same_content(&self, other: &FileEntry) -> bool {
+        self.size == other.size && self.hash == other.hash
+    }
+}
+
+/// Size and mtime of a file, all that is read of build files that aren't hashed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    pub modified: String,
+}
+
+impl FileStat {
+    pub fn of(file: &Path) -> Result<Self> {
+        let metadata = file.metadata()?;
+        Ok(FileStat {
+            size: metadata.len(),
+            modified: DateTime::<Utc>::from(metadata.modified()?).to_rfc3339(),
+        })
+    }
+}
+
+/// Difference of a file between two manifests.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change<'a> {
+    Added(&'a str, &'a FileEntry),
+    Modified(&'a str, &'a FileEntry, &'a FileEntry),
+    Removed(&'a str, &'a FileEntry),
+}
+
+/// Per-device map of build file path to its pushed [`FileEntry`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Manifest {
+    files: BTreeMap<String, FileEntry>,
+}
+
+impl Manifest {
+    pub fn get(&self, path: &str) -> Option<&FileEntry> {
+        self.files.get(path)
+    }
+
+    pub fn insert(&mut self, path: String, entry: FileEntry) {
+        self.files.insert(path, entry);
+    }
+
+    /// Put back `previous` for `path`, dropping the entry if there was none.
+    pub fn restore(&mut self, path: &str, previous: Option<&FileEntry>) {
+        match previous {
+            Some(entry) => self.insert(path.to_owned(), entry.clone()),
+            None => {
+                self.files.remove(path);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Files added, modified or removed in `current` compared to this manifest.
+    pub fn changes<'a>(&'a self, current: &'a Manifest) -> Vec<Change<'a>> {
+        let mut changes: Vec<_> = current
+            .files
+            .iter()
+            .filter_map(|(path, after)| match self.files.get(path) {
+                None => Some(Change::Added(path, after)),
+                Some(before) if !before.same_content(after) => {
+                    Some(Change::Modified(path, before, after))
+                }
+                Some(_) => None,
+            })
+            .collect();
+        changes.extend(
+            self.files
+                .iter()
+                .filter(|(path, _)| !current.files.contains_key(*path))
+                .map(|(path, before)| Change::Removed(path, before)),
+        );
+        changes
+    }
+
+    /// Whether `entry` differs from what was recorded for `path`.
+    pub fn is_changed(&self, path: &str, entry: &FileEntry) -> bool {
+        !self
+            .get(path)
+            .is_some_and(|previous| previous.same_content(entry))
+    }
+
+    /// Compute the entry of `file`, reusing the hash recorded under `path` if
+    /// the file's size and mtime are unchanged.
+    pub fn fingerprint(&self, path: &str, file: &Path) -> Result<FileEntry> {
+        let FileStat { size, modified } = FileStat::of(file)?;
+
+        if let Some(previous) = self.get(path) {
+            if previous.size == size && previous.modified == modified {
+                return Ok(previous.clone());
+            }
+        }
+
+        Ok(FileEntry {
+            size,
+            modified,
+            hash: hash_file(file)?,
+        })
+    }
+}
+
+/// BLAKE3 digest of the file's content, hex encoded.
+pub fn hash_file(file: &Path) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(file)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// SHA-256 digest of the file's content, hex encoded as printed by `sha256sum`.
+pub fn sha256_file(file: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(file)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64, modified: &str, hash: &str) -> FileEntry {
+        FileEntry {
+            size,
+            modified: modified.to_owned(),
+            hash: hash.to_owned(),
+        }
+    }
+
+    fn manifest(entries: &[(&str, FileEntry)]) -> Manifest {
+        let mut manifest = Manifest::default();
+        for (path, entry) in entries {
+            manifest.insert(path.to_string(), entry.clone());
+        }
+        manifest
+    }
+
+    #[test]
+    fn changes_compare_content_not_mtime() {
+        let kept = entry(1, "2024-01-01T00:00:00+00:00", "a");
+        let touched = entry(1, "2024-02-01T00:00:00+00:00", "a");
+        let before = entry(2, "2024-01-01T00:00:00+00:00", "b");
+        let after = entry(2, "2024-01-01T00:00:00+00:00", "c");
+        let removed = entry(3, "2024-01-01T00:00:00+00:00", "d");
+        let added = entry(4, "2024-01-01T00:00:00+00:00", "e");
+
+        let previous = manifest(&[
+            ("kept", kept),
+            ("modified", before.clone()),
+            ("removed", removed.clone()),
+        ]);
+        let current = manifest(&[
+            ("added", added.clone()),
+            ("kept", touched),
+            ("modified", after.clone()),
+        ]);
+        assert_eq!(
+            previous.changes(&current),
+            [
+                Change::Added("added", &added),
+                Change::Modified("modified", &before, &after),
+                Change::Removed("removed", &removed),
+            ]
+        );
+        assert!(current.changes(&current).is_empty());
+    }
+}
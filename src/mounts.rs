@@ -0,0 +1,193 @@
+use crate::{report::failure_message, transport::DeviceTransport};
+use log::{debug, error, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::Path,
+};
+
+/// Device partitions files are pushed to, remounted writable on their own.
+pub const PARTITIONS: [&str; 5] = ["/", "/system", "/vendor", "/sys_prod", "/chip_prod"];
+
+/// Mount points of the device and whether they are writable, from `/proc/mounts`.
+#[derive(Default)]
+pub struct Mounts {
+    writable: HashMap<String, bool>,
+}
+
+impl Mounts {
+    /// Parse the content of `/proc/mounts`. A mount point mounted several times takes
+    /// the options of the last mount, which hides the others.
+    pub fn parse(proc_mounts: &str) -> Self {
+        let writable = proc_mounts
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let mount_point = fields.nth(1)?;
+                let options = fields.nth(1)?;
+                Some((
+                    mount_point.to_owned(),
+                    options.split(',').any(|option| option == "rw"),
+                ))
+            })
+            .collect();
+        Mounts { writable }
+    }
+
+    pub fn read(transport: &dyn DeviceTransport, connect_key: &str) -> io::Result<Self> {
+        let output = transport.shell(connect_key, &["cat", "/proc/mounts"])?;
+        if !output.success() {
+            return Err(io::Error::other(failure_message(&output)));
+        }
+        Ok(Self::parse(&output.stdout))
+    }
+
+    /// The partition of [`PARTITIONS`] holding `device_path`: the deepest one that
+    /// contains it and is mounted on its own.
+    pub fn partition_of(&self, device_path: &Path) -> &'static str {
+        PARTITIONS
+            .iter()
+            .rev()
+            .find(|partition| {
+                device_path.starts_with(partition)
+                    && (**partition == "/" || self.writable.contains_key(**partition))
+            })
+            .copied()
+            .unwrap_or("/")
+    }
+
+    /// Whether `partition` is mounted writable, `None` if it isn't listed.
+    pub fn is_writable(&self, partition: &str) -> Option<bool> {
+        self.writable.get(partition).copied()
+    }
+}
+
+/// Remount the partitions holding `device_paths` writable, verifying the result
+/// in `/proc/mounts`. Returns the device paths on partitions that stay read-only,
+/// with the reason.
+pub fn remount_writable<'a>(
+    transport: &dyn DeviceTransport,
+    connect_key: &str,
+    device_paths: &[&'a Path],
+) -> HashMap<&'a Path, String> {
+    let mounts = Mounts::read(transport, connect_key)
+        .inspect_err(|error| {
+            warn!("fail to read /proc/mounts of {connect_key}, remounting unchecked: {error}")
+        })
+        .ok();
+    let partition_of = |device_path: &Path| match &mounts {
+        Some(mounts) => mounts.partition_of(device_path),
+        None => Mounts::default().partition_of(device_path),
+    };
+
+    let partitions: BTreeSet<_> = device_paths.iter().map(|path| partition_of(path)).collect();
+    let mut failures = BTreeMap::new();
+    for partition in &partitions {
+        if mounts
+            .as_ref()
+            .and_then(|mounts| mounts.is_writable(partition))
+            == Some(true)
+        {
+            debug!("{partition} of {connect_key} is already writable");
+            continue;
+        }
+        // hdc shell exits with 0 either way, what mount printed is kept as the reason
+        match transport.remount(connect_key, partition) {
+            Ok(output) if output.success() && output.stdout.trim().is_empty() => {}
+            Ok(output) => {
+                failures.insert(*partition, failure_message(&output));
+            }
+            Err(error) => {
+                failures.insert(*partition, error.to_string());
+            }
+        }
+    }
+
+    // the remount call can report success without changing anything
+    let remounted = mounts.as_ref().and_then(|_| {
+        Mounts::read(transport, connect_key)
+            .inspect_err(|error| warn!("fail to read /proc/mounts of {connect_key}: {error}"))
+            .ok()
+    });
+    let read_only: BTreeMap<_, _> = partitions
+        .into_iter()
+        .filter_map(|partition| {
+            let failure = failures.remove(partition);
+            match remounted
+                .as_ref()
+                .and_then(|mounts| mounts.is_writable(partition))
+            {
+                Some(true) => None,
+                Some(false) => Some((
+                    partition,
+                    failure.unwrap_or_else(|| String::from("still mounted read-only")),
+                )),
+                None => failure.map(|reason| (partition, reason)),
+            }
+        })
+        .collect();
+
+    for (partition, reason) in &read_only {
+        error!(
+            "{partition} of {connect_key} can't be remounted writable, not sending the files under it: {reason}"
+        );
+    }
+    device_paths
+        .iter()
+        .filter_map(|path| {
+            read_only.get(partition_of(path)).map(|reason| {
+                (
+                    *path,
+                    format!("{} is read-only: {reason}", partition_of(path)),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_MOUNTS: &str = "\
+/dev/root / ext4 ro,seclabel,relatime 0 0
+tmpfs /dev tmpfs rw,seclabel,nosuid,relatime,mode=755 0 0
+/dev/block/by-name/vendor /vendor ext4 ro,seclabel,relatime 0 0
+/dev/block/by-name/vendor /vendor ext4 rw,seclabel,relatime 0 0
+/dev/block/by-name/chip_prod /chip_prod ext4 ro,seclabel,relatime 0 0
+";
+
+    #[test]
+    fn parse_takes_the_last_mount_of_a_mount_point() {
+        let mounts = Mounts::parse(PROC_MOUNTS);
+        assert_eq!(mounts.is_writable("/"), Some(false));
+        assert_eq!(mounts.is_writable("/dev"), Some(true));
+        assert_eq!(mounts.is_writable("/vendor"), Some(true));
+        assert_eq!(mounts.is_writable("/chip_prod"), Some(false));
+        assert_eq!(mounts.is_writable("/system"), None);
+    }
+
+    #[test]
+    fn partition_of_is_the_deepest_mounted_partition() {
+        let mounts = Mounts::parse(PROC_MOUNTS);
+        // /system isn't mounted on its own here, it is part of /
+        assert_eq!(
+            mounts.partition_of(Path::new("/system/lib64/libfoo.z.so")),
+            "/"
+        );
+        assert_eq!(
+            mounts.partition_of(Path::new("/vendor/bin/audio_host")),
+            "/vendor"
+        );
+        assert_eq!(
+            mounts.partition_of(Path::new("/chip_prod/etc/foo")),
+            "/chip_prod"
+        );
+        // a prefix of the name isn't the partition
+        assert_eq!(mounts.partition_of(Path::new("/vendorx/foo")), "/");
+        assert_eq!(
+            Mounts::default().partition_of(Path::new("/vendor/bin/audio_host")),
+            "/"
+        );
+    }
+}
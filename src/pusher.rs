@@ -0,0 +1,1980 @@
+use crate::{
+    abi::{device_archs, needed_libraries, Arch},
+    archive::write_archive,
+    cli::{OutputFormat, PushOptions},
+    config::Target,
+    history::{self, HistoryFile, PushSession},
+    index::PackageIndex,
+    manifest::{sha256_file, Change, FileStat, Manifest},
+    mounts::remount_writable,
+    record::{Record, RecordScope, Records},
+    report::{failure_message, FileReport, JsonReport, JsonReports, PushReport},
+    restart::Restarts,
+    scan::Scanner,
+    snapshot::{new_push_id, Snapshot, SnapshotEntry},
+    transport::{shell_quote, CallOutput, DeviceTransport},
+    workdir::file_name_safe,
+};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, ExitCode},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Device directory batch archives are sent to before extracting them.
+const BATCH_DEVICE_DIR: &str = "/data/local/tmp";
+
+/// How often files failing verification are sent again.
+const VERIFY_RETRIES: usize = 2;
+
+/// How often a rebooting device is looked for.
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a rebooting device may stay listed before it is assumed to be back.
+const BOOT_GONE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Printed on the device before each device path that doesn't exist.
+const PATH_ABSENT: &str = "oh-pusher-absent:";
+
+/// Device paths passed to a single shell command.
+const DEVICE_PATH_CHUNK: usize = 64;
+
+/// Build files that are new to a device and the device paths they go to.
+struct PushPlan {
+    /// Record of the device before this run, `None` if it has never been seen.
+    record: Option<Record>,
+    previous: Manifest,
+    /// State of every scanned build file that maps to a device path.
+    manifest: Manifest,
+    build_file_map: BTreeMap<PathBuf, Vec<PathBuf>>,
+    /// New build files that map to no device path, which aren't hashed.
+    unmapped: BTreeMap<PathBuf, FileStat>,
+}
+
+impl PushPlan {
+    /// Whether there are files to send. A device seen for the first time only gets
+    /// its record initialized.
+    fn is_pending(&self) -> bool {
+        self.record.is_some() && !self.build_file_map.is_empty()
+    }
+}
+
+/// Pushes one build to one device.
+///
+/// Pushers of several devices share the records and the transport; the caller
+/// holds the [`WorkdirLock`](crate::workdir::WorkdirLock) while they are alive.
+pub struct BuildFilePusher {
+    target: Target,
+    workdir: PathBuf,
+    scope: RecordScope,
+    records: Arc<Mutex<Records>>,
+    transport: Arc<dyn DeviceTransport>,
+    scanner: Scanner,
+    restarts: Restarts,
+}
+
+impl BuildFilePusher {
+    pub fn new(
+        target: Target,
+        workdir: PathBuf,
+        records: Arc<Mutex<Records>>,
+        transport: Arc<dyn DeviceTransport>,
+    ) -> Self {
+        debug!("Working directory: {}", workdir.as_path().display());
+
+        let scanner =
+            Scanner::new(&target.scan).unwrap_or_else(|error| panic!("invalid scan glob: {error}"));
+        let restarts = Restarts::new(&target.restart)
+            .unwrap_or_else(|error| panic!("invalid restart glob: {error}"));
+
+        let scope = RecordScope::new(
+            &target.connect_key,
+            &target.build_dir,
+            &target.build_package_dir,
+        );
+        BuildFilePusher {
+            target,
+            scope,
+            workdir,
+            records,
+            transport,
+            scanner,
+            restarts,
+        }
+    }
+
+    pub fn connect_key(&self) -> &str {
+        &self.target.connect_key
+    }
+
+    /// Print the files the next push would send.
+    fn print_status(&self, plan: &PushPlan) {
+        if plan.record.is_none() {
+            info!(
+                "No record for device {}, the first push only records the current build",
+                self.target.connect_key
+            );
+        } else if plan.build_file_map.is_empty() && plan.unmapped.is_empty() {
+            info!("No new files since last push");
+        } else {
+            self.print_plan(plan, false);
+        }
+    }
+
+    /// Print how the build changed since the last push to the device.
+    pub fn diff(&self) -> ExitCode {
+        let plan = self.plan(false);
+
+        if plan.record.is_none() {
+            info!("No record for device {}", self.target.connect_key);
+            return ExitCode::SUCCESS;
+        }
+
+        let changes = plan.previous.changes(&plan.manifest);
+        if changes.is_empty() {
+            info!("No changes since last push");
+        }
+        for change in changes {
+            match change {
+                Change::Added(path, entry) => println!("A  {path} ({} bytes)", entry.size),
+                Change::Modified(path, before, after) => {
+                    println!("M  {path} ({} -> {} bytes)", before.size, after.size)
+                }
+                Change::Removed(path, _) => println!("D  {path}"),
+            }
+        }
+
+        ExitCode::SUCCESS
+    }
+
+    /// Send the planned files if `send` is set and record what made it.
+    ///
+    /// With `dry_run` the transport is expected to only print the calls, so the
+    /// record is left untouched.
+    fn execute(&self, plan: &PushPlan, send: bool, options: &PushOptions) -> PushReport {
+        let send = send && plan.is_pending();
+
+        let report = if send {
+            let started = Instant::now();
+            let push_id = new_push_id(&self.target.connect_key);
+            let mut session = PushSession::new(&push_id, &self.scope, Utc::now().to_rfc3339());
+            let mut report = PushReport::default();
+            let build_file_map = self.check_abi(&plan.build_file_map, &mut report, options);
+            // nothing is overwritten on a dry run
+            if !options.no_backup && !options.dry_run && !build_file_map.is_empty() {
+                self.backup(&push_id, &build_file_map, usize::from(options.jobs));
+                session.snapshot = true;
+            }
+            let sent = self.push_files(&build_file_map, options);
+            report.results.extend(sent.results);
+            // a dry run never writes anything to compare against
+            if options.verify && !options.dry_run {
+                self.verify(&mut report, usize::from(options.jobs));
+            }
+            if report.succeeded() > 0 {
+                self.run_post_push();
+                if !options.no_restart {
+                    self.restart(&report, options);
+                }
+                if options.reboot {
+                    // rebooting into a half-updated system may leave it unable to boot
+                    if report.failed() > 0 {
+                        warn!(
+                            "Not rebooting {}, {} files failed",
+                            self.target.connect_key,
+                            report.failed()
+                        );
+                    } else if let Err(error) = self.reboot(options, started) {
+                        warn!("{error}");
+                        report.errors.push(error);
+                    }
+                }
+            }
+            if !options.dry_run {
+                self.append_history(session, plan, &report);
+            }
+            report
+        } else {
+            PushReport::default()
+        };
+
+        if options.dry_run {
+            if plan.record.is_none() {
+                info!(
+                    "Dry run, no record for device {}, nothing would be sent",
+                    self.target.connect_key
+                );
+            }
+            return report;
+        }
+
+        // modified records
+        if send || plan.record.is_none() {
+            self.update_record(plan, &report, send);
+        }
+
+        report
+    }
+
+    /// Record the current build as pushed without sending anything.
+    pub fn rewrite_record(&self) -> ExitCode {
+        let plan = self.plan(false);
+        self.update_record(&plan, &PushReport::default(), false);
+        ExitCode::SUCCESS
+    }
+
+    /// Add the shared libraries the pending ELF files need, directly or through
+    /// each other, that the build package directory has but the device doesn't.
+    fn add_dependencies(&self, plan: &mut PushPlan, index: &OnceLock<PackageIndex>) {
+        let connect_key = &self.target.connect_key;
+        let package_dir = self.package_dir();
+        let index = self.index(index);
+
+        // libraries that are pushed anyway
+        let mut handled: HashSet<(OsString, Arch)> = HashSet::new();
+        let pushed: HashSet<OsString> = plan
+            .build_file_map
+            .values()
+            .flatten()
+            .filter_map(|device_path| device_path.file_name().map(OsStr::to_owned))
+            .collect();
+
+        let mut binaries: Vec<PathBuf> = plan.build_file_map.keys().cloned().collect();
+        while !binaries.is_empty() {
+            // device path of each library needed, with the package file and the binary needing it
+            let mut wanted: BTreeMap<PathBuf, (PathBuf, PathBuf)> = BTreeMap::new();
+            for binary in binaries.drain(..) {
+                let Ok(Some(arch)) = Arch::of_file(&binary) else {
+                    continue;
+                };
+                let needed = match needed_libraries(&binary) {
+                    Ok(needed) => needed,
+                    Err(error) => {
+                        warn!(
+                            "fail to read the dependencies of {}: {error}",
+                            binary.display()
+                        );
+                        continue;
+                    }
+                };
+                for name in needed.iter().map(OsString::from) {
+                    if pushed.contains(&name) || !handled.insert((name.clone(), arch)) {
+                        continue;
+                    }
+                    // lib and lib64 often carry a library of the same name
+                    for device_path in index.lookup(&name) {
+                        let library =
+                            package_dir.join(device_path.strip_prefix("/").unwrap_or(device_path));
+                        if !matches!(Arch::of_file(&library), Ok(Some(found)) if found == arch) {
+                            continue;
+                        }
+                        wanted.insert(device_path.clone(), (library, binary.clone()));
+                    }
+                }
+            }
+            if wanted.is_empty() {
+                break;
+            }
+
+            let paths: Vec<_> = wanted.keys().map(PathBuf::as_path).collect();
+            let Some(absent) = self.device_absent(&paths) else {
+                warn!("can't tell which libraries {connect_key} lacks, not adding dependencies");
+                return;
+            };
+            for (device_path, (library, binary)) in wanted {
+                if !absent.contains(&device_path) {
+                    continue;
+                }
+                info!(
+                    "{} needs {}, which is missing on {connect_key}",
+                    binary.display(),
+                    device_path.display()
+                );
+                // the scan may not cover the package directory, the library is taken into the record here
+                let key = self.manifest_key(&library);
+                let entry = plan
+                    .previous
+                    .fingerprint(&key, &library)
+                    .expect("fingerprint dependency fail");
+                plan.manifest.insert(key, entry);
+                plan.build_file_map
+                    .entry(library.clone())
+                    .or_default()
+                    .push(device_path);
+                binaries.push(library);
+            }
+        }
+    }
+
+    fn plan(&self, force_update: bool) -> PushPlan {
+        self.plan_with_index(force_update, &OnceLock::new())
+    }
+
+    /// Plan with the package index held by `index`, which pushers of the same
+    /// package directory share so that it is built only once.
+    fn plan_with_index(&self, force_update: bool, index: &OnceLock<PackageIndex>) -> PushPlan {
+        let record = self.records.lock().unwrap().get(&self.scope).cloned();
+
+        let previous = record
+            .as_ref()
+            .map(|record| record.files.clone())
+            .unwrap_or_default();
+
+        let watermark = record.as_ref().map(|record| {
+            DateTime::parse_from_rfc3339(&record.last_modified_date).expect("iso time format error")
+        });
+        // records written before file hashes were tracked only carry a watermark
+        let legacy = record
+            .as_ref()
+            .is_some_and(|record| record.files.is_empty());
+
+        let package_dir = self.package_dir();
+
+        // scan directories
+        let all_files = self.scanner.files(&self.target.build_dir, &package_dir);
+
+        debug!("len of all files: {}", all_files.len());
+
+        // only files outside the package directory need the index, build it on first use
+        let index = || self.index(index);
+
+        // map files to device paths first, a file may land in several places on the device;
+        // only mapped files are hashed, hashes of files with unchanged size and mtime are reused
+        let mut manifest = Manifest::default();
+        let mut build_file_map = BTreeMap::new();
+        let mut unmapped = BTreeMap::new();
+        for file in all_files {
+            // files inside the package directory already sit at their device path
+            let device_paths = match file.strip_prefix(&package_dir) {
+                Ok(relative) => vec![Path::new("/").join(relative)],
+                Err(_) => index()
+                    .lookup(file.file_name().expect("get build file name"))
+                    .to_vec(),
+            };
+
+            // unmapped files are only reported while newer than the watermark
+            if device_paths.is_empty() {
+                let stat = FileStat::of(&file).expect("stat candidate file fail");
+                let modified =
+                    DateTime::parse_from_rfc3339(&stat.modified).expect("iso time format error");
+                if force_update || watermark.is_none_or(|watermark| modified > watermark) {
+                    unmapped.insert(file, stat);
+                }
+                continue;
+            }
+
+            let key = self.manifest_key(&file);
+            let entry = previous
+                .fingerprint(&key, &file)
+                .expect("fingerprint candidate file fail");
+            let changed = match watermark.filter(|_| legacy) {
+                Some(watermark) => {
+                    DateTime::parse_from_rfc3339(&entry.modified).expect("iso time format error")
+                        > watermark
+                }
+                None => previous.is_changed(&key, &entry),
+            };
+            if force_update || changed {
+                build_file_map.insert(file, device_paths);
+            }
+            manifest.insert(key, entry);
+        }
+
+        debug!("len of build file map: {}", build_file_map.len());
+        debug!("len of unmapped files: {}", unmapped.len());
+
+        PushPlan {
+            record,
+            previous,
+            manifest,
+            build_file_map,
+            unmapped,
+        }
+    }
+
+    /// Print the planned files, to stderr if stdout is kept for machine readable output.
+    fn print_plan(&self, plan: &PushPlan, to_stderr: bool) {
+        let print = |line: String| {
+            if to_stderr {
+                eprintln!("{line}");
+            } else {
+                println!("{line}");
+            }
+        };
+        if !plan.build_file_map.is_empty() {
+            info!("Found the following new files: ");
+        }
+        for (build_file, device_paths) in &plan.build_file_map {
+            for device_path in device_paths {
+                print(format!(
+                    "{} -> {}",
+                    build_file.display(),
+                    device_path.display()
+                ));
+            }
+            if device_paths.len() > 1 {
+                warn!(
+                    "{} matches {} device paths, it will be sent to each of them",
+                    build_file.display(),
+                    device_paths.len()
+                );
+            }
+        }
+        if !plan.unmapped.is_empty() {
+            info!("The following new files are unmapped and will not be sent: ");
+            for build_file in plan.unmapped.keys() {
+                print(build_file.display().to_string());
+            }
+        }
+    }
+
+    fn json_report<'a>(
+        &'a self,
+        plan: &'a PushPlan,
+        report: &'a PushReport,
+        dry_run: bool,
+    ) -> JsonReport<'a> {
+        let file_report = |path: &PathBuf, destinations: &[PathBuf]| {
+            let entry = plan
+                .manifest
+                .get(&self.manifest_key(path))
+                .expect("new file missing from manifest");
+            FileReport {
+                path: path.clone(),
+                size: entry.size,
+                modified: entry.modified.clone(),
+                destinations: destinations.to_vec(),
+            }
+        };
+
+        JsonReport {
+            device: &self.target.connect_key,
+            watermark: plan
+                .record
+                .as_ref()
+                .map(|record| record.last_modified_date.as_str()),
+            first_push: plan.record.is_none(),
+            dry_run,
+            new_files: plan
+                .build_file_map
+                .iter()
+                .map(|(path, destinations)| file_report(path, destinations))
+                .collect(),
+            unmapped: plan
+                .unmapped
+                .iter()
+                .map(|(path, stat)| FileReport {
+                    path: path.clone(),
+                    size: stat.size,
+                    modified: stat.modified.clone(),
+                    destinations: Vec::new(),
+                })
+                .collect(),
+            results: &report.results,
+            errors: &report.errors,
+        }
+    }
+
+    /// Store the planned build state of the device, except for files that failed to push.
+    fn update_record(&self, plan: &PushPlan, report: &PushReport, pushed: bool) {
+        // files that did not make it keep their previous entry so they are retried next time
+        let failed_files = report.failed_files();
+        let mut files = plan.manifest.clone();
+        for build_file in &failed_files {
+            let key = self.manifest_key(build_file);
+            files.restore(&key, plan.previous.get(&key));
+        }
+
+        // the watermark is the newest file taken into the record, it never goes back
+        let new_modified_date = plan
+            .build_file_map
+            .keys()
+            .filter(|f| !failed_files.contains(f.as_path()))
+            .filter_map(|f| plan.manifest.get(&self.manifest_key(f)))
+            .map(|entry| entry.modified.as_str())
+            .chain(plan.unmapped.values().map(|stat| stat.modified.as_str()))
+            .chain(
+                plan.record
+                    .as_ref()
+                    .map(|record| record.last_modified_date.as_str()),
+            )
+            .map(|modified| DateTime::parse_from_rfc3339(modified).expect("iso time format error"))
+            .max()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_default();
+
+        let last_push = if pushed {
+            Some(Utc::now().to_rfc3339())
+        } else {
+            plan.record.as_ref().and_then(|r| r.last_push.clone())
+        };
+
+        let mut records = self.records.lock().unwrap();
+        records.upsert(Record {
+            last_modified_date: new_modified_date.to_rfc3339(),
+            last_push,
+            files,
+            ..Record::new(&self.scope)
+        });
+
+        // update record file
+        records.save().expect("write json to record file");
+
+        info!("update record files of device {}", self.target.connect_key);
+    }
+
+    /// The entries of `build_file_map` to send: native binaries built for an
+    /// architecture the device doesn't run are failed in `report` instead, unless
+    /// mismatches are allowed.
+    fn check_abi(
+        &self,
+        build_file_map: &BTreeMap<PathBuf, Vec<PathBuf>>,
+        report: &mut PushReport,
+        options: &PushOptions,
+    ) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+        let connect_key = &self.target.connect_key;
+        let native: Vec<_> = build_file_map
+            .keys()
+            .filter_map(|build_file| match Arch::of_file(build_file) {
+                Ok(arch) => arch.map(|arch| (build_file, arch)),
+                Err(error) => {
+                    warn!(
+                        "fail to read the ELF header of {}: {error}",
+                        build_file.display()
+                    );
+                    None
+                }
+            })
+            .collect();
+        if native.is_empty() {
+            return build_file_map.clone();
+        }
+        let Some((abi, archs)) = device_archs(&*self.transport, connect_key) else {
+            warn!(
+                "can't tell the ABI of {connect_key}, not checking {} native files",
+                native.len()
+            );
+            return build_file_map.clone();
+        };
+        debug!("ABI of {connect_key}: {abi}");
+
+        let mut accepted = build_file_map.clone();
+        let mut rejected = 0;
+        for (build_file, arch) in native {
+            if archs.contains(&arch) {
+                continue;
+            }
+            if options.allow_abi_mismatch {
+                warn!(
+                    "{} is {arch}, which doesn't match the ABI {abi} of {connect_key}",
+                    build_file.display()
+                );
+                continue;
+            }
+            let error = format!("{arch} doesn't match the device ABI {abi}");
+            for device_path in accepted.remove(build_file).unwrap_or_default() {
+                report.record_error(build_file, &device_path, error.clone());
+            }
+            rejected += 1;
+        }
+        if rejected > 0 {
+            error!(
+                "Not sending {rejected} files built for another architecture than the ABI {abi} of {connect_key}, --allow-abi-mismatch sends them anyway"
+            );
+        }
+        accepted
+    }
+
+    /// Remount the device writable and send every build file to each of its device paths.
+    fn push_files(
+        &self,
+        build_file_map: &BTreeMap<PathBuf, Vec<PathBuf>>,
+        options: &PushOptions,
+    ) -> PushReport {
+        let mut sends: Vec<(&Path, &Path)> = build_file_map
+            .iter()
+            .flat_map(|(build_file, device_paths)| {
+                device_paths
+                    .iter()
+                    .map(move |device_path| (build_file.as_path(), device_path.as_path()))
+            })
+            .collect();
+
+        if sends.is_empty() {
+            return PushReport::default();
+        }
+
+        // files on partitions that stay read-only would only fail one by one
+        let device_paths: Vec<_> = sends.iter().map(|(_, device_path)| *device_path).collect();
+        let read_only = remount_writable(&*self.transport, &self.target.connect_key, &device_paths);
+        let mut report = PushReport::default();
+        sends.retain(
+            |(build_file, device_path)| match read_only.get(device_path) {
+                Some(error) => {
+                    report.record_error(build_file, device_path, error.clone());
+                    false
+                }
+                None => true,
+            },
+        );
+        if sends.is_empty() {
+            return report;
+        }
+
+        let sent = if options.batch && sends.len() > 1 {
+            self.send_batch(&sends, options)
+                .inspect_err(|error| {
+                    warn!(
+                        "fail to push an archive to {}, sending files one by one: {error}",
+                        self.target.connect_key
+                    )
+                })
+                .ok()
+        } else {
+            None
+        };
+        let sent = sent.unwrap_or_else(|| self.send_each(&sends, usize::from(options.jobs)));
+        report.results.extend(sent.results);
+        report
+    }
+
+    /// Send every file on its own, `jobs` at a time.
+    fn send_each(&self, sends: &[(&Path, &Path)], jobs: usize) -> PushReport {
+        let mut report = PushReport::default();
+        in_parallel(
+            sends,
+            jobs,
+            |(build_file, device_path)| {
+                self.transport
+                    .send_file(&self.target.connect_key, build_file, device_path)
+            },
+            |index, outcome| {
+                let (build_file, device_path) = sends[index];
+                report.record(build_file, device_path, outcome);
+                let result = report.results.last().expect("just recorded");
+                info!(
+                    "[{}/{}] {} {} {}",
+                    report.results.len(),
+                    sends.len(),
+                    self.target.connect_key,
+                    device_path.display(),
+                    if result.succeeded() { "ok" } else { "FAIL" }
+                );
+            },
+        );
+        report
+    }
+
+    /// Send every file in one tar archive and extract it on the device.
+    fn send_batch(
+        &self,
+        sends: &[(&Path, &Path)],
+        options: &PushOptions,
+    ) -> io::Result<PushReport> {
+        let extension = if options.compress { "tar.gz" } else { "tar" };
+        let key = file_name_safe(&self.target.connect_key);
+        let local = self.workdir.join(format!("batch-{key}.{extension}"));
+        let remote = Path::new(BATCH_DEVICE_DIR)
+            .join(format!("oh-pusher-{}-{key}.{extension}", process::id()));
+
+        let size = write_archive(&local, sends, options.compress)?;
+        info!(
+            "Sending {} files to {} in an archive of {size} bytes",
+            sends.len(),
+            self.target.connect_key
+        );
+        let extracted = self.extract_archive(&local, &remote, options);
+        // the printed commands of a dry run refer to the archive, it is kept for them
+        if options.dry_run {
+            info!(
+                "Dry run, kept the archive at {} for the printed commands",
+                local.display()
+            );
+        } else if let Err(error) = fs::remove_file(&local) {
+            warn!("fail to remove {}: {error}", local.display());
+        }
+        let output = extracted?;
+
+        let mut report = PushReport::default();
+        for (build_file, device_path) in sends {
+            report.record(build_file, device_path, Ok(output.clone()));
+        }
+        Ok(report)
+    }
+
+    /// Send the archive at `local` to `remote`, extract it at `/` and remove it again.
+    fn extract_archive(
+        &self,
+        local: &Path,
+        remote: &Path,
+        options: &PushOptions,
+    ) -> io::Result<CallOutput> {
+        let connect_key = &self.target.connect_key;
+        let sent = self.transport.send_file(connect_key, local, remote)?;
+        if !sent.success() {
+            return Err(io::Error::other(failure_message(&sent)));
+        }
+
+        let command = format!(
+            "tar -x{}f {} -C /",
+            if options.compress { "z" } else { "" },
+            shell_quote(&remote.to_string_lossy())
+        );
+        let extracted = self.transport.shell_checked(connect_key, &command);
+        let remote = remote.to_string_lossy();
+        match self.transport.shell(connect_key, &["rm", "-f", &remote]) {
+            Ok(output) if output.success() => {}
+            Ok(output) => warn!("fail to remove {remote}: {}", failure_message(&output)),
+            Err(error) => warn!("fail to remove {remote}: {error}"),
+        }
+
+        let extracted = extracted?;
+        if extracted.success() {
+            Ok(extracted)
+        } else {
+            Err(io::Error::other(format!(
+                "fail to extract {remote}: {}",
+                failure_message(&extracted)
+            )))
+        }
+    }
+
+    /// Save the device copy of every destination to the snapshot `push_id` before it
+    /// is overwritten, receiving `jobs` files at a time.
+    fn backup(&self, push_id: &str, build_file_map: &BTreeMap<PathBuf, Vec<PathBuf>>, jobs: usize) {
+        let mut destinations: BTreeMap<&Path, Vec<String>> = BTreeMap::new();
+        for (build_file, device_paths) in build_file_map {
+            for device_path in device_paths {
+                destinations
+                    .entry(device_path)
+                    .or_default()
+                    .push(self.manifest_key(build_file));
+            }
+        }
+        let paths: Vec<_> = destinations.keys().copied().collect();
+        // only a path the device says is missing is removed on rollback
+        let absent = self.device_absent(&paths).unwrap_or_default();
+        let existing: Vec<_> = paths
+            .iter()
+            .copied()
+            .filter(|path| !absent.contains(*path))
+            .collect();
+        let mut saved = HashSet::new();
+        in_parallel(
+            &existing,
+            jobs,
+            |device_path| self.save_device_file(push_id, device_path),
+            |index, ok| {
+                if ok {
+                    saved.insert(existing[index]);
+                }
+            },
+        );
+
+        let mut snapshot = Snapshot::new(push_id, &self.scope);
+        for (device_path, build_files) in destinations {
+            snapshot.files.push(SnapshotEntry {
+                device_path: device_path.to_path_buf(),
+                build_files,
+                existed: !absent.contains(device_path),
+                saved: saved.contains(device_path),
+            });
+        }
+        snapshot
+            .save(&self.workdir)
+            .expect("write snapshot to working directory");
+        let pruned = Snapshot::prune(&self.workdir, &self.target.connect_key);
+        if pruned > 0 {
+            debug!(
+                "removed {pruned} old snapshots of {}",
+                self.target.connect_key
+            );
+        }
+
+        info!(
+            "Saved {} device files of {} to snapshot {push_id}, undo the push with `rollback {push_id}`",
+            snapshot.files.iter().filter(|entry| entry.saved).count(),
+            self.target.connect_key
+        );
+    }
+
+    /// Add the outcome of the push to the history, only warning on failure.
+    fn append_history(&self, mut session: PushSession, plan: &PushPlan, report: &PushReport) {
+        session.finished = Utc::now().to_rfc3339();
+        session.files = report
+            .results
+            .iter()
+            .map(|result| HistoryFile {
+                build_file: result.build_file.clone(),
+                device_path: result.device_path.clone(),
+                hash: plan
+                    .manifest
+                    .get(&self.manifest_key(&result.build_file))
+                    .map(|entry| entry.hash.clone()),
+                error: result.error.clone(),
+            })
+            .collect();
+
+        // the record lock also keeps pushes to other devices from writing at the same time
+        let _records = self.records.lock().unwrap();
+        match history::append(&self.workdir, &session) {
+            Ok(()) => info!(
+                "Recorded push {} to {} in the history",
+                session.push_id, self.target.connect_key
+            ),
+            Err(error) => warn!(
+                "fail to record push {} in the history: {error}",
+                session.push_id
+            ),
+        }
+    }
+
+    /// Receive `device_path` into the snapshot `push_id`, warning if that fails.
+    fn save_device_file(&self, push_id: &str, device_path: &Path) -> bool {
+        let local = Snapshot::file_path(&self.workdir, push_id, device_path);
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent).expect("create snapshot directory");
+        }
+        match self
+            .transport
+            .recv_file(&self.target.connect_key, device_path, &local)
+        {
+            Ok(output) if output.success() => true,
+            Ok(output) => {
+                warn!(
+                    "fail to back up {}, it can't be rolled back: {}",
+                    device_path.display(),
+                    failure_message(&output)
+                );
+                false
+            }
+            Err(error) => {
+                warn!(
+                    "fail to back up {}, it can't be rolled back: {error}",
+                    device_path.display()
+                );
+                false
+            }
+        }
+    }
+
+    /// Which of `paths` the device says don't exist, `None` if it can't be asked.
+    /// Paths it gives no answer for are taken to exist.
+    fn device_absent(&self, paths: &[&Path]) -> Option<HashSet<PathBuf>> {
+        let mut absent = HashSet::new();
+        for paths in paths.chunks(DEVICE_PATH_CHUNK) {
+            let paths: Vec<_> = paths
+                .iter()
+                .map(|path| shell_quote(&path.to_string_lossy()))
+                .collect();
+            // dangling links count as existing, they are replaced as well
+            let command = format!(
+                "for p in {}; do [ -e \"$p\" ] || [ -L \"$p\" ] || echo \"{PATH_ABSENT}$p\"; done",
+                paths.join(" ")
+            );
+            match self
+                .transport
+                .shell_checked(&self.target.connect_key, &command)
+            {
+                Ok(output) if output.success() => absent.extend(
+                    output
+                        .stdout
+                        .lines()
+                        .filter_map(|line| line.strip_prefix(PATH_ABSENT))
+                        .map(PathBuf::from),
+                ),
+                Ok(output) => {
+                    warn!(
+                        "fail to list files on {}: {}",
+                        self.target.connect_key,
+                        failure_message(&output)
+                    );
+                    return None;
+                }
+                Err(error) => {
+                    warn!("fail to list files on {}: {error}", self.target.connect_key);
+                    return None;
+                }
+            }
+        }
+        Some(absent)
+    }
+
+    /// Compare the device checksum of every written device path with its build file,
+    /// sending mismatched files again up to [`VERIFY_RETRIES`] times. Files that still
+    /// don't match are failed in `report`, keeping them pending for the next push.
+    fn verify(&self, report: &mut PushReport, jobs: usize) {
+        // the last build file sent to a device path is the one that should be there
+        let written: BTreeMap<PathBuf, PathBuf> = report
+            .results
+            .iter()
+            .filter(|result| result.succeeded())
+            .map(|result| (result.device_path.clone(), result.build_file.clone()))
+            .collect();
+        let mut mismatched: Vec<(&Path, &Path, Option<String>)> = written
+            .iter()
+            .map(|(device_path, build_file)| {
+                let hash = sha256_file(build_file)
+                    .inspect_err(|error| warn!("fail to hash {}: {error}", build_file.display()))
+                    .ok();
+                (build_file.as_path(), device_path.as_path(), hash)
+            })
+            .collect();
+
+        for attempt in 0..=VERIFY_RETRIES {
+            if attempt > 0 {
+                info!(
+                    "Sending {} mismatched files to {} again, attempt {attempt} of {VERIFY_RETRIES}",
+                    mismatched.len(),
+                    self.target.connect_key
+                );
+                let sends: Vec<_> = mismatched
+                    .iter()
+                    .map(|(build_file, device_path, _)| (*build_file, *device_path))
+                    .collect();
+                self.send_each(&sends, jobs);
+            }
+
+            let paths: Vec<_> = mismatched.iter().map(|(_, path, _)| *path).collect();
+            let checksums = self.device_checksums(&paths);
+            mismatched.retain(|(_, device_path, hash)| {
+                hash.is_none() || checksums.get(*device_path) != hash.as_ref()
+            });
+            if mismatched.is_empty() {
+                break;
+            }
+        }
+
+        for (build_file, device_path, _) in &mismatched {
+            warn!(
+                "{} on {} doesn't match {}",
+                device_path.display(),
+                self.target.connect_key,
+                build_file.display()
+            );
+            report.fail(device_path, "checksum mismatch on the device");
+        }
+        info!(
+            "Verified {} of {} files on {}",
+            written.len() - mismatched.len(),
+            written.len(),
+            self.target.connect_key
+        );
+    }
+
+    /// SHA-256 checksums of `paths` on the device, missing for unreadable paths.
+    fn device_checksums(&self, paths: &[&Path]) -> HashMap<PathBuf, String> {
+        let mut checksums = HashMap::new();
+        for paths in paths.chunks(DEVICE_PATH_CHUNK) {
+            let command = std::iter::once(String::from("sha256sum"))
+                .chain(
+                    paths
+                        .iter()
+                        .map(|path| shell_quote(&path.to_string_lossy())),
+                )
+                .collect::<Vec<_>>()
+                .join(" ");
+            let output = match self.transport.shell(&self.target.connect_key, &[&command]) {
+                Ok(output) => output,
+                Err(error) => {
+                    warn!(
+                        "fail to run sha256sum on {}: {error}",
+                        self.target.connect_key
+                    );
+                    continue;
+                }
+            };
+            // errors of unreadable paths are mixed in, they don't parse as checksums
+            for line in output.stdout.lines() {
+                if let Some((hash, path)) = line.trim_end().split_once("  ") {
+                    checksums.insert(PathBuf::from(path), hash.to_owned());
+                }
+            }
+        }
+        checksums
+    }
+
+    /// Run the configured post-push commands on the device, only warning on failure.
+    fn run_post_push(&self) {
+        for command in &self.target.post_push {
+            info!("Running post-push command: {command}");
+            match self
+                .transport
+                .shell_checked(&self.target.connect_key, command)
+            {
+                Ok(output) if output.success() => {}
+                Ok(output) => warn!(
+                    "post-push command `{command}` failed: {}",
+                    failure_message(&output)
+                ),
+                Err(error) => warn!("fail to run post-push command `{command}`: {error}"),
+            }
+        }
+    }
+
+    /// Restart what the written device paths belong to, only warning on failure.
+    /// A due reboot is left to `--reboot`.
+    fn restart(&self, report: &PushReport, options: &PushOptions) {
+        let written = report
+            .results
+            .iter()
+            .filter(|result| result.succeeded())
+            .map(|result| result.device_path.as_path());
+        for action in self.restarts.actions(written) {
+            let Some(command) = action.command() else {
+                // only rebooted when asked to, see `execute`
+                if !options.reboot {
+                    warn!(
+                        "{} needs a reboot for the pushed files to take effect, --reboot reboots it",
+                        self.target.connect_key
+                    );
+                }
+                return;
+            };
+            info!("Restarting on {}: {command}", self.target.connect_key);
+            match self
+                .transport
+                .shell_checked(&self.target.connect_key, &command)
+            {
+                Ok(output) if output.success() => {}
+                Ok(output) => warn!("`{command}` failed: {}", failure_message(&output)),
+                Err(error) => warn!("fail to run `{command}`: {error}"),
+            }
+        }
+    }
+
+    /// Reboot the device and wait until it is listed again and passes the health
+    /// check, reporting the time since the push `started`. Fails with what went wrong.
+    fn reboot(&self, options: &PushOptions, started: Instant) -> Result<(), String> {
+        let connect_key = &self.target.connect_key;
+        info!("Rebooting {connect_key}");
+        match self.transport.reboot(connect_key) {
+            Ok(output) if output.success() => {}
+            Ok(output) => {
+                return Err(format!(
+                    "fail to reboot {connect_key}: {}",
+                    failure_message(&output)
+                ))
+            }
+            Err(error) => return Err(format!("fail to reboot {connect_key}: {error}")),
+        }
+        // a dry run has no device coming back
+        if options.dry_run {
+            return Ok(());
+        }
+
+        let rebooted = Instant::now();
+        let deadline = rebooted + Duration::from_secs(options.boot_timeout);
+        let listed = || {
+            self.transport
+                .list_targets()
+                .is_ok_and(|targets| targets.iter().any(|target| target == connect_key))
+        };
+        // the device stays listed for a moment, seeing it before it went away means nothing
+        let gone_by = rebooted + BOOT_GONE_TIMEOUT;
+        while listed() && Instant::now() < gone_by {
+            thread::sleep(BOOT_POLL_INTERVAL);
+        }
+        while !listed() {
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "{connect_key} didn't come back within {}s",
+                    options.boot_timeout
+                ));
+            }
+            thread::sleep(BOOT_POLL_INTERVAL);
+        }
+        info!(
+            "{connect_key} is back after {:.1}s",
+            rebooted.elapsed().as_secs_f64()
+        );
+
+        if let Some(health_check) = &self.target.health_check {
+            loop {
+                let passed = self
+                    .transport
+                    .shell_checked(connect_key, health_check)
+                    .is_ok_and(|output| output.success());
+                if passed {
+                    info!("Health check `{health_check}` passed on {connect_key}");
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    return Err(format!(
+                        "health check `{health_check}` didn't pass on {connect_key} within {}s",
+                        options.boot_timeout
+                    ));
+                }
+                thread::sleep(BOOT_POLL_INTERVAL);
+            }
+        }
+        info!(
+            "{connect_key} is ready {:.1}s after the push started",
+            started.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    /// The package index held by `index`, loading or building it on first use.
+    fn index<'a>(&self, index: &'a OnceLock<PackageIndex>) -> &'a PackageIndex {
+        index.get_or_init(|| {
+            let index = PackageIndex::load_or_build(
+                &self.package_dir(),
+                &self.workdir,
+                self.target.rebuild_index,
+            )
+            .expect("index build package directory");
+            debug!("len of package index: {}", index.len());
+            index
+        })
+    }
+
+    fn package_dir(&self) -> PathBuf {
+        self.target.build_dir.join(&self.target.build_package_dir)
+    }
+
+    /// Key of a build file in the manifest, relative to the build directory.
+    fn manifest_key(&self, file: &Path) -> String {
+        file.strip_prefix(&self.target.build_dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Run `work` on every item, `jobs` at a time, handing the results to `done` in the
+/// order of `items` as they come in.
+fn in_parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut done: impl FnMut(usize, R),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let sender = sender.clone();
+            let (next, work) = (&next, &work);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                if sender.send((index, work(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // hold back results that finished early
+        let mut finished = BTreeMap::new();
+        let mut done_count = 0;
+        for (index, result) in receiver {
+            finished.insert(index, result);
+            while let Some(result) = finished.remove(&done_count) {
+                done(done_count, result);
+                done_count += 1;
+            }
+        }
+    });
+}
+
+/// Print the files the next push would send to each device.
+pub fn status_devices(pushers: &[BuildFilePusher], output: OutputFormat) -> ExitCode {
+    let plans = plan_devices(pushers, false, false);
+
+    if output == OutputFormat::Json {
+        let nothing_sent = PushReport::default();
+        let reports: Vec<_> = pushers
+            .iter()
+            .zip(&plans)
+            .map(|(pusher, plan)| pusher.json_report(plan, &nothing_sent, false))
+            .collect();
+        print_json_reports(reports);
+    } else {
+        for (pusher, plan) in pushers.iter().zip(&plans) {
+            if pushers.len() > 1 {
+                info!("Device {}:", pusher.connect_key());
+            }
+            pusher.print_status(plan);
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Print how the build changed since the last push to each device.
+pub fn diff_devices(pushers: &[BuildFilePusher]) -> ExitCode {
+    for pusher in pushers {
+        if pushers.len() > 1 {
+            info!("Device {}:", pusher.connect_key());
+        }
+        pusher.diff();
+    }
+    ExitCode::SUCCESS
+}
+
+/// Send new files to every device at once after a single confirmation, and
+/// record per device what made it.
+pub fn push_devices(
+    pushers: &[BuildFilePusher],
+    options: &PushOptions,
+    output: OutputFormat,
+) -> ExitCode {
+    // a dry run can't tell which libraries the device lacks
+    if options.with_deps && options.dry_run {
+        info!("Dry run, missing dependencies are not looked up");
+    }
+    let plans = plan_devices(
+        pushers,
+        options.force_update,
+        options.with_deps && !options.dry_run,
+    );
+    let several = pushers.len() > 1;
+
+    // decide whether to send files
+    let mut send = false;
+    if plans.iter().any(PushPlan::is_pending) {
+        for (pusher, plan) in pushers.iter().zip(&plans) {
+            if !plan.is_pending() {
+                continue;
+            }
+            if options.dry_run {
+                info!(
+                    "Dry run, {} new files would be sent to {}, {} are unmapped",
+                    plan.build_file_map.len(),
+                    pusher.connect_key(),
+                    plan.unmapped.len()
+                );
+            } else if output == OutputFormat::Text || !options.yes {
+                if several {
+                    info!("Device {}:", pusher.connect_key());
+                }
+                // the JSON report comes after the answer, the files to confirm go to stderr
+                pusher.print_plan(plan, output == OutputFormat::Json);
+            }
+        }
+        send = options.dry_run || options.yes || decide_send_by_user(output);
+    }
+
+    // every device gets its own thread, they only share the record
+    let reports: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = pushers
+            .iter()
+            .zip(&plans)
+            .map(|(pusher, plan)| scope.spawn(move || pusher.execute(plan, send, options)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("push thread panicked"))
+            .collect()
+    });
+
+    if output == OutputFormat::Json {
+        let reports: Vec<_> = pushers
+            .iter()
+            .zip(&plans)
+            .zip(&reports)
+            .map(|((pusher, plan), report)| pusher.json_report(plan, report, options.dry_run))
+            .collect();
+        print_json_reports(reports);
+    } else if send && !options.dry_run {
+        for (pusher, report) in pushers.iter().zip(&reports) {
+            if several {
+                println!("Device {}:", pusher.connect_key());
+            }
+            report.print_summary();
+        }
+        if several {
+            println!("Device summary:");
+            for (pusher, report) in pushers.iter().zip(&reports) {
+                println!(
+                    "  {:<4}  {}: {} succeeded, {} failed{}",
+                    if report.is_failure() { "FAIL" } else { "OK" },
+                    pusher.connect_key(),
+                    report.succeeded(),
+                    report.failed(),
+                    report
+                        .errors
+                        .iter()
+                        .map(|error| format!(", {error}"))
+                        .collect::<String>()
+                );
+            }
+        }
+    }
+
+    if reports.iter().any(PushReport::is_failure) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Plan every device in parallel, each one scans the build on its own while the
+/// package index is built once per package directory. With `with_deps` the missing
+/// libraries of the pending files are added.
+fn plan_devices(pushers: &[BuildFilePusher], force_update: bool, with_deps: bool) -> Vec<PushPlan> {
+    let indexes: HashMap<PathBuf, OnceLock<PackageIndex>> = pushers
+        .iter()
+        .map(|pusher| (pusher.package_dir(), OnceLock::new()))
+        .collect();
+    thread::scope(|scope| {
+        let handles: Vec<_> = pushers
+            .iter()
+            .map(|pusher| {
+                let index = &indexes[&pusher.package_dir()];
+                scope.spawn(move || {
+                    let mut plan = pusher.plan_with_index(force_update, index);
+                    if with_deps && plan.is_pending() {
+                        pusher.add_dependencies(&mut plan, index);
+                    }
+                    plan
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("plan thread panicked"))
+            .collect()
+    })
+}
+
+fn print_json_reports(devices: Vec<JsonReport>) {
+    let json = serde_json::to_string_pretty(&JsonReports { devices });
+    println!("{}", json.expect("convert report to json"));
+}
+
+/// Ask the user whether to proceed.
+pub fn decide_send_by_user(output: OutputFormat) -> bool {
+    // keep stdout parseable when it carries the JSON report
+    if output == OutputFormat::Json {
+        eprint!("Do you want to proceed? [Y/n] ");
+        let _ = io::stderr().flush();
+    } else {
+        print!("Do you want to proceed? [Y/n] ");
+        let _ = io::stdout().flush();
+    }
+
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .expect("Failed to read line");
+
+    let input = input.trim();
+
+    matches!(input, "y" | "Y" | "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{ScanConfig, Settings},
+        scan::ScanMode,
+        testutil::TempDir,
+        transport::{FakeTransport, TransportCall, SHELL_SUCCEEDED},
+    };
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        options: PushOptions,
+    }
+
+    fn options(args: &[&str]) -> PushOptions {
+        Cli::parse_from(std::iter::once("push").chain(args.iter().copied())).options
+    }
+
+    fn pusher(
+        build: &TempDir,
+        workdir: &TempDir,
+        transport: &Arc<FakeTransport>,
+    ) -> BuildFilePusher {
+        pushers(build, workdir, transport, &["device"], false)
+            .pop()
+            .unwrap()
+    }
+
+    /// Pushers of `connect_keys` sharing the record, as for `-t a,b`.
+    fn pushers(
+        build: &TempDir,
+        workdir: &TempDir,
+        transport: &Arc<FakeTransport>,
+        connect_keys: &[&str],
+        rebuild_index: bool,
+    ) -> Vec<BuildFilePusher> {
+        let records = Arc::new(Mutex::new(Records::load(workdir.path()).unwrap()));
+        connect_keys
+            .iter()
+            .map(|connect_key| {
+                let target = Settings {
+                    build_dir: Some(build.path().to_path_buf()),
+                    ..Settings::default()
+                }
+                .into_target(connect_key.to_string(), rebuild_index)
+                .unwrap();
+                BuildFilePusher::new(
+                    target,
+                    workdir.path().to_path_buf(),
+                    Arc::clone(&records),
+                    Arc::clone(transport) as Arc<dyn DeviceTransport>,
+                )
+            })
+            .collect()
+    }
+
+    /// A build with `names` in `out` and at their device path in the package directory.
+    fn build(names: &[&str]) -> TempDir {
+        let build = TempDir::new();
+        for name in names {
+            build.write(&format!("packages/phone/system/lib64/{name}"), b"packaged");
+            build.write(&format!("out/rk3568/{name}"), b"v1");
+        }
+        build
+    }
+
+    fn push(pusher: &BuildFilePusher) -> PushReport {
+        let plan = pusher.plan(false);
+        pusher.execute(&plan, true, &options(&["-y"]))
+    }
+
+    fn sent(transport: &FakeTransport) -> Vec<(PathBuf, PathBuf)> {
+        transport
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                TransportCall::SendFile { local, remote, .. } => Some((local, remote)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn failure(stdout: &str) -> Option<io::Result<CallOutput>> {
+        Some(Ok(CallOutput {
+            code: Some(1),
+            stdout: stdout.to_owned(),
+            stderr: String::new(),
+        }))
+    }
+
+    #[test]
+    fn first_push_only_records_then_changed_files_are_sent() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+
+        push(&pusher);
+        assert!(transport.calls().is_empty());
+        assert!(!pusher.plan(false).is_pending());
+
+        let build_file = build.write("out/rk3568/libfoo.z.so", b"v2");
+        let report = push(&pusher);
+        assert_eq!(report.succeeded(), 1);
+        assert!(transport.calls().contains(&TransportCall::Remount {
+            connect_key: String::from("device"),
+            mount_point: String::from("/"),
+        }));
+        assert_eq!(
+            sent(&transport),
+            [(build_file, PathBuf::from("/system/lib64/libfoo.z.so"))]
+        );
+        assert!(!pusher.plan(false).is_pending());
+    }
+
+    #[test]
+    fn only_mapped_files_are_recorded() {
+        let build = build(&["libfoo.z.so"]);
+        build.write("base/README.md", b"docs");
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+
+        let plan = pusher.plan(false);
+        assert_eq!(
+            plan.unmapped.into_keys().collect::<Vec<_>>(),
+            [build.path().join("base/README.md")]
+        );
+        push(&pusher);
+
+        let records = pusher.records.lock().unwrap();
+        let files = &records.get(&pusher.scope).unwrap().files;
+        assert_eq!(files.len(), 1);
+        assert!(files.get("out/rk3568/libfoo.z.so").is_some());
+        drop(records);
+        assert!(pusher.plan(false).unmapped.is_empty());
+    }
+
+    #[test]
+    fn failed_files_are_not_recorded() {
+        let build = build(&["libbar.z.so", "libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        let failed = build.write("out/rk3568/libbar.z.so", b"v2");
+        transport.respond(|call| match call {
+            TransportCall::SendFile { remote, .. } if remote.ends_with("libbar.z.so") => {
+                failure("[Fail]Error opening file")
+            }
+            _ => None,
+        });
+        let report = push(&pusher);
+        assert_eq!((report.succeeded(), report.failed()), (1, 1));
+        assert_eq!(sent(&transport).len(), 2);
+
+        let pending: Vec<_> = pusher.plan(false).build_file_map.into_keys().collect();
+        assert_eq!(pending, [failed]);
+    }
+
+    #[test]
+    fn files_on_read_only_partitions_are_not_sent() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        transport.respond(|call| match call {
+            TransportCall::Remount { .. } => failure("mount: '/' not in /proc/mounts"),
+            _ => None,
+        });
+        let report = push(&pusher);
+        assert_eq!(report.failed(), 1);
+        assert!(sent(&transport).is_empty());
+        assert!(pusher.plan(false).is_pending());
+    }
+
+    #[test]
+    fn json_report_lists_the_new_files_after_a_push() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+
+        let plan = pusher.plan(false);
+        let report = pusher.execute(&plan, true, &options(&["-y"]));
+        let json = pusher.json_report(&plan, &report, false);
+        assert!(json.first_push);
+        assert_eq!(json.new_files.len(), 1);
+
+        // a new file without a previous entry fails
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        build.write("packages/phone/system/lib64/libnew.z.so", b"packaged");
+        build.write("out/rk3568/libnew.z.so", b"v1");
+        transport.respond(|call| match call {
+            TransportCall::SendFile { remote, .. } if remote.ends_with("libnew.z.so") => {
+                failure("[Fail]Error opening file")
+            }
+            _ => None,
+        });
+        let plan = pusher.plan(false);
+        let report = pusher.execute(&plan, true, &options(&["-y"]));
+        let json = pusher.json_report(&plan, &report, false);
+        assert_eq!(json.new_files.len(), 2);
+        assert_eq!(json.results.len(), 2);
+
+        // a single device has the same shape as several
+        let json = serde_json::to_value(JsonReports {
+            devices: vec![json],
+        })
+        .unwrap();
+        assert_eq!(json["devices"][0]["device"], "device");
+    }
+
+    #[test]
+    fn devices_share_one_package_index() {
+        let build = build(&["libbar.z.so", "libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pushers = pushers(&build, &workdir, &transport, &["a", "b", "c", "d"], true);
+
+        let plans = plan_devices(&pushers, false, false);
+        assert_eq!(plans.len(), 4);
+        for plan in &plans {
+            assert_eq!(plan.build_file_map.len(), 2);
+        }
+        let index_files = fs::read_dir(workdir.path().join("package_index"))
+            .unwrap()
+            .count();
+        assert_eq!(index_files, 1);
+    }
+
+    #[test]
+    fn dry_run_keeps_the_batch_archive() {
+        let build = build(&["libbar.z.so", "libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libbar.z.so", b"v2");
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        let plan = pusher.plan(false);
+        let report = pusher.execute(&plan, true, &options(&["-y", "--dry-run", "--batch"]));
+        assert_eq!(report.succeeded(), 2);
+        let archive = match &sent(&transport)[..] {
+            [(archive, _)] => archive.clone(),
+            sent => panic!("expected one archive to be sent, got {sent:?}"),
+        };
+        assert!(archive.exists());
+    }
+
+    #[test]
+    fn unlisted_device_paths_are_not_assumed_missing() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        transport.respond(|call| match call {
+            TransportCall::Shell { command, .. } if command[0].starts_with("for p in") => {
+                failure("[Fail]ExecuteCommand need connect-key?")
+            }
+            _ => None,
+        });
+        push(&pusher);
+
+        let snapshot = &Snapshot::list(workdir.path())[0];
+        assert!(snapshot.files.iter().all(|entry| entry.existed));
+    }
+
+    #[test]
+    fn silent_device_paths_are_not_assumed_missing() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        // a device shell that prints nothing, not even the success marker
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        transport.respond(|call| match call {
+            TransportCall::Shell { command, .. } if command[0].starts_with("for p in") => {
+                Some(Ok(CallOutput {
+                    code: Some(0),
+                    stdout: String::new(),
+                    stderr: String::new(),
+                }))
+            }
+            _ => None,
+        });
+        push(&pusher);
+
+        let snapshot = &Snapshot::list(workdir.path())[0];
+        assert!(snapshot
+            .files
+            .iter()
+            .all(|entry| entry.existed && entry.saved));
+    }
+
+    #[test]
+    fn reboot_rule_waits_for_every_file() {
+        let build = TempDir::new();
+        for name in ["system/etc/init/foo.cfg", "system/lib64/libbar.z.so"] {
+            build.write(&format!("packages/phone/{name}"), b"packaged");
+            build.write(
+                &format!("out/rk3568/{}", name.rsplit('/').next().unwrap()),
+                b"v1",
+            );
+        }
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/foo.cfg", b"v2");
+        build.write("out/rk3568/libbar.z.so", b"v2");
+        transport.respond(|call| match call {
+            TransportCall::SendFile { remote, .. } if remote.ends_with("libbar.z.so") => {
+                failure("[Fail]Error opening file")
+            }
+            _ => None,
+        });
+        let plan = pusher.plan(false);
+        pusher.execute(&plan, true, &options(&["-y", "--reboot"]));
+        assert!(!transport
+            .calls()
+            .iter()
+            .any(|call| matches!(call, TransportCall::Reboot { .. })));
+    }
+
+    #[test]
+    fn failed_reboot_fails_the_device() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        transport.respond(|call| match call {
+            TransportCall::Reboot { .. } => failure("[Fail]ExecuteCommand need connect-key?"),
+            _ => None,
+        });
+        let plan = pusher.plan(false);
+        let report = pusher.execute(&plan, true, &options(&["-y", "--reboot"]));
+        assert_eq!((report.succeeded(), report.failed()), (1, 0));
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.is_failure());
+    }
+
+    #[test]
+    fn reboot_rule_needs_the_reboot_flag() {
+        let build = build(&["libsamgr.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libsamgr.z.so", b"v2");
+        let report = push(&pusher);
+        assert_eq!(report.failed(), 0);
+        assert!(!transport
+            .calls()
+            .iter()
+            .any(|call| matches!(call, TransportCall::Reboot { .. })));
+    }
+
+    #[test]
+    fn restart_commands_report_through_a_marker() {
+        let build = TempDir::new();
+        build.write("packages/phone/system/bin/foundation", b"packaged");
+        build.write("out/rk3568/foundation", b"v1");
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/foundation", b"v2");
+        push(&pusher);
+        let restarts: Vec<_> = transport
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                TransportCall::Shell { command, .. } if command[0].contains("begetctl") => {
+                    Some(command[0].clone())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            restarts,
+            [format!(
+                "begetctl stop_service foundation; begetctl start_service foundation && echo {SHELL_SUCCEEDED}"
+            )]
+        );
+    }
+
+    /// A little-endian 64-bit ELF file for arm64 linking against `needed`, with
+    /// just the program headers locating its dynamic section.
+    fn elf(needed: &[&str]) -> Vec<u8> {
+        const PHDRS: u64 = 64;
+        const DYNAMIC: u64 = PHDRS + 2 * 56;
+        let strtab_offset = DYNAMIC + (needed.len() as u64 + 3) * 16;
+        let mut strtab = vec![0u8];
+        let mut dynamic = Vec::new();
+        for name in needed {
+            dynamic.extend([1, strtab.len() as u64]); // DT_NEEDED
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+        dynamic.extend([5, strtab_offset, 10, strtab.len() as u64, 0, 0]); // DT_STRTAB, DT_STRSZ, DT_NULL
+        let size = strtab_offset + strtab.len() as u64;
+
+        let mut bytes = b"\x7fELF\x02\x01\x01".to_vec();
+        bytes.resize(16, 0);
+        let half = |bytes: &mut Vec<u8>, value: u16| bytes.extend(value.to_le_bytes());
+        let word = |bytes: &mut Vec<u8>, value: u32| bytes.extend(value.to_le_bytes());
+        let xword = |bytes: &mut Vec<u8>, value: u64| bytes.extend(value.to_le_bytes());
+        half(&mut bytes, 3); // ET_DYN
+        half(&mut bytes, goblin::elf::header::EM_AARCH64);
+        word(&mut bytes, 1);
+        xword(&mut bytes, 0); // entry
+        xword(&mut bytes, PHDRS);
+        xword(&mut bytes, 0); // no section headers
+        word(&mut bytes, 0);
+        for value in [64, 56, 2, 64, 0, 0] {
+            half(&mut bytes, value);
+        }
+        // PT_LOAD of the whole file, PT_DYNAMIC
+        for (kind, offset, size) in [(1, 0, size), (2, DYNAMIC, (needed.len() as u64 + 3) * 16)] {
+            word(&mut bytes, kind);
+            word(&mut bytes, 4); // PF_R
+            for value in [offset, offset, offset, size, size, 8] {
+                xword(&mut bytes, value);
+            }
+        }
+        for value in dynamic {
+            xword(&mut bytes, value);
+        }
+        bytes.extend(strtab);
+        bytes
+    }
+
+    #[test]
+    fn missing_dependencies_are_added_and_recorded() {
+        let build = TempDir::new();
+        build.write("packages/phone/system/bin/foo", &elf(&[]));
+        build.write("packages/phone/system/lib64/libdep.z.so", &elf(&[]));
+        let binary = build.write("out/rk3568/foo", &elf(&[]));
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pushers = pushers(&build, &workdir, &transport, &["device"], false);
+        push(&pushers[0]);
+
+        fs::write(&binary, elf(&["libdep.z.so", "libc.so"])).unwrap();
+        transport.respond(|call| match call {
+            TransportCall::Shell { command, .. } if command[0].starts_with("for p in") => {
+                Some(Ok(CallOutput {
+                    code: Some(0),
+                    stdout: format!("{PATH_ABSENT}/system/lib64/libdep.z.so\n{SHELL_SUCCEEDED}\n"),
+                    stderr: String::new(),
+                }))
+            }
+            _ => None,
+        });
+        let plans = plan_devices(&pushers, false, true);
+        let library = build.path().join("packages/phone/system/lib64/libdep.z.so");
+        assert_eq!(
+            plans[0].build_file_map.keys().collect::<Vec<_>>(),
+            [&binary, &library]
+        );
+
+        let report = pushers[0].execute(&plans[0], true, &options(&["-y", "--with-deps"]));
+        assert_eq!(report.succeeded(), 2);
+        let json = pushers[0].json_report(&plans[0], &report, false);
+        assert_eq!(json.new_files.len(), 2);
+        let records = pushers[0].records.lock().unwrap();
+        let files = &records.get(&pushers[0].scope).unwrap().files;
+        assert!(files
+            .get("packages/phone/system/lib64/libdep.z.so")
+            .is_some());
+    }
+
+    #[test]
+    fn packages_mode_pushes_files_to_their_package_path() {
+        let build = TempDir::new();
+        let library = build.write("packages/phone/system/lib64/libfoo.z.so", b"v1");
+        let config = build.write("packages/phone/vendor/etc/foo.json", b"v1");
+        build.write("out/rk3568/libfoo.z.so", b"v1");
+        let workdir = TempDir::new();
+        let target = Settings {
+            build_dir: Some(build.path().to_path_buf()),
+            scan: ScanConfig {
+                mode: Some(ScanMode::Packages),
+                ..ScanConfig::default()
+            },
+            ..Settings::default()
+        }
+        .into_target(String::from("device"), false)
+        .unwrap();
+        let pusher = BuildFilePusher::new(
+            target,
+            workdir.path().to_path_buf(),
+            Arc::new(Mutex::new(Records::load(workdir.path()).unwrap())),
+            Arc::new(FakeTransport::default()),
+        );
+
+        let plan = pusher.plan(false);
+        assert_eq!(
+            plan.build_file_map.into_iter().collect::<Vec<_>>(),
+            [
+                (library, vec![PathBuf::from("/system/lib64/libfoo.z.so")]),
+                (config, vec![PathBuf::from("/vendor/etc/foo.json")]),
+            ]
+        );
+        assert!(plan.unmapped.is_empty());
+        // the index isn't needed for files that sit at their device path
+        assert!(!workdir.path().join("package_index").exists());
+    }
+
+    /// Answer `sha256sum` with `hash` for every path, in its output format.
+    fn checksums(
+        transport: &FakeTransport,
+        hash: impl Fn(usize) -> String + Send + Sync + 'static,
+    ) {
+        let calls = AtomicUsize::new(0);
+        transport.respond(move |call| match call {
+            TransportCall::Shell { command, .. } if command[0].starts_with("sha256sum ") => {
+                let hash = hash(calls.fetch_add(1, Ordering::Relaxed));
+                let stdout = command[0]
+                    .split_whitespace()
+                    .skip(1)
+                    .map(|path| format!("{hash}  {path}\n"))
+                    .collect();
+                Some(Ok(CallOutput {
+                    code: Some(0),
+                    stdout,
+                    stderr: String::new(),
+                }))
+            }
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn mismatched_files_are_sent_again_until_they_match() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        let build_file = build.write("out/rk3568/libfoo.z.so", b"v2");
+        let hash = sha256_file(&build_file).unwrap();
+        checksums(&transport, move |call| match call {
+            0 => String::from("0").repeat(64),
+            _ => hash.clone(),
+        });
+        let plan = pusher.plan(false);
+        let report = pusher.execute(&plan, true, &options(&["-y", "--verify"]));
+
+        assert_eq!((report.succeeded(), report.failed()), (1, 0));
+        assert_eq!(sent(&transport).len(), 2);
+        assert!(!pusher.plan(false).is_pending());
+    }
+
+    #[test]
+    fn files_that_never_match_stay_pending() {
+        let build = build(&["libfoo.z.so"]);
+        let workdir = TempDir::new();
+        let transport = Arc::new(FakeTransport::default());
+        let pusher = pusher(&build, &workdir, &transport);
+        push(&pusher);
+
+        build.write("out/rk3568/libfoo.z.so", b"v2");
+        checksums(&transport, |_| String::from("0").repeat(64));
+        let plan = pusher.plan(false);
+        let report = pusher.execute(&plan, true, &options(&["-y", "--verify"]));
+
+        assert_eq!((report.succeeded(), report.failed()), (0, 1));
+        assert_eq!(
+            report.results[0].error.as_deref(),
+            Some("checksum mismatch on the device")
+        );
+        assert_eq!(sent(&transport).len(), 1 + VERIFY_RETRIES);
+        assert!(pusher.plan(false).is_pending());
+    }
+}
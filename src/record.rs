@@ -0,0 +1,266 @@
+use crate::{manifest::Manifest, workdir::write_atomic};
+use chrono::Utc;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+pub const RECORD_FILE: &str = "build_record.json";
+
+/// Schema version of the record file written by this build.
+const RECORD_VERSION: u32 = 2;
+
+/// Device and build a record belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordScope {
+    pub connectkey: String,
+    pub build_dir: PathBuf,
+    pub build_package_dir: String,
+}
+
+impl RecordScope {
+    /// Scope of `build_dir`, canonicalized so that different spellings of the same
+    /// checkout share a record.
+    pub fn new(connect_key: &str, build_dir: &Path, build_package_dir: &str) -> Self {
+        RecordScope {
+            connectkey: connect_key.to_owned(),
+            build_dir: fs::canonicalize(build_dir).unwrap_or_else(|_| build_dir.to_path_buf()),
+            build_package_dir: build_package_dir.to_owned(),
+        }
+    }
+}
+
+/// What has been pushed to one device from one build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub connectkey: String,
+    /// Canonical build directory, `None` for records migrated from version 1.
+    #[serde(default)]
+    pub build_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub build_package_dir: Option<String>,
+    pub last_modified_date: String,
+    #[serde(default)]
+    pub last_push: Option<String>,
+    #[serde(default)]
+    pub files: Manifest,
+}
+
+impl Record {
+    pub fn new(scope: &RecordScope) -> Self {
+        Record {
+            connectkey: scope.connectkey.clone(),
+            build_dir: Some(scope.build_dir.clone()),
+            build_package_dir: Some(scope.build_package_dir.clone()),
+            last_modified_date: String::new(),
+            last_push: None,
+            files: Manifest::default(),
+        }
+    }
+
+    pub fn in_scope(&self, scope: &RecordScope) -> bool {
+        self.connectkey == scope.connectkey
+            && self.build_dir.as_ref() == Some(&scope.build_dir)
+            && self.build_package_dir.as_ref() == Some(&scope.build_package_dir)
+    }
+
+    /// Migrated records don't know which build they came from.
+    pub fn is_unscoped(&self) -> bool {
+        self.build_dir.is_none()
+    }
+}
+
+/// On-disk layout of the record file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecordFile {
+    Versioned {
+        version: u32,
+        records: Vec<Record>,
+    },
+    /// Version 1: a flat list keyed by connect key only.
+    Flat(Option<Vec<Record>>),
+}
+
+#[derive(Serialize)]
+struct RecordFileRef<'a> {
+    version: u32,
+    records: &'a [Record],
+}
+
+/// Content of the record file in the working directory.
+pub struct Records {
+    path: PathBuf,
+    entries: Vec<Record>,
+}
+
+impl Records {
+    /// Read the record file, setting a corrupted one aside and starting afresh. A
+    /// record file of a newer version is left alone and fails with
+    /// [`ErrorKind::Unsupported`].
+    pub fn load(workdir: &Path) -> Result<Self> {
+        let path = workdir.join(RECORD_FILE);
+        let entries = if path.exists() {
+            match Self::read(&path) {
+                Ok(entries) => entries,
+                Err(error) if error.kind() == ErrorKind::Unsupported => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!("{}: {error}, update this tool to use it", path.display()),
+                    ));
+                }
+                Err(error) => {
+                    let backup = path.with_extension(format!(
+                        "json.corrupt-{}",
+                        Utc::now().format("%Y%m%d%H%M%S")
+                    ));
+                    warn!(
+                        "record file {} is corrupted ({error}), moved it to {} and starting fresh",
+                        path.display(),
+                        backup.display()
+                    );
+                    fs::rename(&path, &backup)?;
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        for record in &entries {
+            debug!(
+                "connectkey: {}, build_dir: {:?}, last_modified_date: {}, files: {}",
+                record.connectkey,
+                record.build_dir,
+                record.last_modified_date,
+                record.files.len()
+            )
+        }
+        Ok(Records { path, entries })
+    }
+
+    fn read(path: &Path) -> Result<Vec<Record>> {
+        match serde_json::from_slice::<RecordFile>(&fs::read(path)?)? {
+            RecordFile::Versioned { version, records } if version <= RECORD_VERSION => Ok(records),
+            RecordFile::Versioned { version, .. } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("record version {version} is newer than supported {RECORD_VERSION}"),
+            )),
+            RecordFile::Flat(records) => {
+                let records = records.unwrap_or_default();
+                if !records.is_empty() {
+                    info!(
+                        "migrating {} device records to version {RECORD_VERSION}",
+                        records.len()
+                    );
+                }
+                Ok(records)
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Record> {
+        self.entries.iter()
+    }
+
+    /// The record of `scope`, falling back to a migrated record of the same device.
+    pub fn get(&self, scope: &RecordScope) -> Option<&Record> {
+        self.entries.iter().find(|x| x.in_scope(scope)).or_else(|| {
+            self.entries
+                .iter()
+                .find(|x| x.is_unscoped() && x.connectkey == scope.connectkey)
+        })
+    }
+
+    /// Replace the record of the same scope, or add it if there is none. A migrated
+    /// record of the same device is taken over.
+    pub fn upsert(&mut self, record: Record) {
+        self.entries
+            .retain(|x| !(x.is_unscoped() && x.connectkey == record.connectkey));
+        match self.entries.iter_mut().find(|x| {
+            x.connectkey == record.connectkey
+                && x.build_dir == record.build_dir
+                && x.build_package_dir == record.build_package_dir
+        }) {
+            Some(existing) => *existing = record,
+            None => self.entries.push(record),
+        }
+    }
+
+    /// Drop the records of `connect_key`, only those of `build_dir` if given.
+    /// Returns how many were dropped.
+    pub fn remove(&mut self, connect_key: &str, build_dir: Option<&Path>) -> usize {
+        let build_dir = build_dir.map(|dir| fs::canonicalize(dir).unwrap_or(dir.to_path_buf()));
+        let len = self.entries.len();
+        self.entries.retain(|x| {
+            x.connectkey != connect_key
+                || build_dir
+                    .as_ref()
+                    .is_some_and(|dir| x.build_dir.as_ref() != Some(dir))
+        });
+        len - self.entries.len()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let records = serde_json::to_string(&RecordFileRef {
+            version: RECORD_VERSION,
+            records: &self.entries,
+        })?;
+        write_atomic(&self.path, records.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn version_1_records_are_migrated() {
+        let workdir = TempDir::new();
+        workdir.write(
+            RECORD_FILE,
+            br#"[{"connectkey":"device","last_modified_date":"2024-01-01T00:00:00+00:00"}]"#,
+        );
+        let build = TempDir::new();
+        let scope = RecordScope::new("device", build.path(), "packages/phone");
+
+        let mut records = Records::load(workdir.path()).unwrap();
+        let migrated = records.get(&scope).unwrap();
+        assert!(migrated.is_unscoped());
+        assert_eq!(migrated.last_modified_date, "2024-01-01T00:00:00+00:00");
+        assert!(migrated.files.is_empty());
+
+        records.upsert(Record::new(&scope));
+        records.save().unwrap();
+
+        let records = Records::load(workdir.path()).unwrap();
+        let content = fs::read_to_string(workdir.path().join(RECORD_FILE)).unwrap();
+        assert!(content.starts_with(&format!(r#"{{"version":{RECORD_VERSION},"#)));
+        assert_eq!(records.iter().count(), 1);
+        assert!(records.get(&scope).unwrap().in_scope(&scope));
+    }
+
+    #[test]
+    fn empty_version_1_file_loads_as_no_records() {
+        let workdir = TempDir::new();
+        workdir.write(RECORD_FILE, b"null");
+        assert_eq!(Records::load(workdir.path()).unwrap().iter().count(), 0);
+    }
+
+    #[test]
+    fn newer_record_files_are_refused_and_kept() {
+        let workdir = TempDir::new();
+        let content = format!(r#"{{"version":{},"records":[]}}"#, RECORD_VERSION + 1);
+        workdir.write(RECORD_FILE, content.as_bytes());
+
+        let error = Records::load(workdir.path()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            fs::read_to_string(workdir.path().join(RECORD_FILE)).unwrap(),
+            content
+        );
+    }
+}
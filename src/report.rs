@@ -0,0 +1,160 @@
+use crate::transport::CallOutput;
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Outcome of sending one build file to one device path.
+#[derive(Debug, Clone, Serialize)]
+pub struct PushResult {
+    /// Empty when `device_path` is removed rather than written.
+    pub build_file: PathBuf,
+    pub device_path: PathBuf,
+    pub error: Option<String>,
+}
+
+impl PushResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Per-file results of a push session.
+#[derive(Debug, Default)]
+pub struct PushReport {
+    pub results: Vec<PushResult>,
+    /// Failures of the device rather than of a file, like a reboot it didn't come
+    /// back from.
+    pub errors: Vec<String>,
+}
+
+impl PushReport {
+    /// Record the outcome of a `file send` call.
+    pub fn record(
+        &mut self,
+        build_file: &Path,
+        device_path: &Path,
+        outcome: io::Result<CallOutput>,
+    ) {
+        let error = match outcome {
+            Ok(output) if output.success() => None,
+            Ok(output) => Some(failure_message(&output)),
+            Err(error) => Some(format!("fail to run hdc: {error}")),
+        };
+        self.results.push(PushResult {
+            build_file: build_file.to_path_buf(),
+            device_path: device_path.to_path_buf(),
+            error,
+        });
+    }
+
+    /// Record a write that was not attempted because of `error`.
+    pub fn record_error(&mut self, build_file: &Path, device_path: &Path, error: String) {
+        self.results.push(PushResult {
+            build_file: build_file.to_path_buf(),
+            device_path: device_path.to_path_buf(),
+            error: Some(error),
+        });
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.succeeded()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+
+    /// Whether a file or the device failed.
+    pub fn is_failure(&self) -> bool {
+        self.failed() > 0 || !self.errors.is_empty()
+    }
+
+    /// Mark every write of `device_path` as failed with `error`.
+    pub fn fail(&mut self, device_path: &Path, error: &str) {
+        for result in &mut self.results {
+            if result.device_path == device_path {
+                result.error = Some(error.to_owned());
+            }
+        }
+    }
+
+    /// Build files with at least one destination that was not written.
+    pub fn failed_files(&self) -> BTreeSet<&Path> {
+        self.results
+            .iter()
+            .filter(|r| !r.succeeded())
+            .map(|r| r.build_file.as_path())
+            .collect()
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "Push summary: {} succeeded, {} failed",
+            self.succeeded(),
+            self.failed()
+        );
+        for result in &self.results {
+            let action = if result.build_file.as_os_str().is_empty() {
+                format!("remove {}", result.device_path.display())
+            } else {
+                format!(
+                    "{} -> {}",
+                    result.build_file.display(),
+                    result.device_path.display()
+                )
+            };
+            match &result.error {
+                None => println!("  OK    {action}"),
+                Some(error) => println!("  FAIL  {action}: {error}"),
+            }
+        }
+        for error in &self.errors {
+            println!("  FAIL  {error}");
+        }
+    }
+}
+
+/// A build file in the JSON output.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: String,
+    pub destinations: Vec<PathBuf>,
+}
+
+/// Document printed with `--output json`, the same for one device as for several.
+#[derive(Debug, Serialize)]
+pub struct JsonReports<'a> {
+    pub devices: Vec<JsonReport<'a>>,
+}
+
+/// One device in the JSON output.
+#[derive(Debug, Serialize)]
+pub struct JsonReport<'a> {
+    pub device: &'a str,
+    /// Watermark of the device record the new files were computed against.
+    pub watermark: Option<&'a str>,
+    pub first_push: bool,
+    pub dry_run: bool,
+    pub new_files: Vec<FileReport>,
+    pub unmapped: Vec<FileReport>,
+    pub results: &'a [PushResult],
+    pub errors: &'a [String],
+}
+
+/// Most useful line of a failed call's output.
+pub fn failure_message(output: &CallOutput) -> String {
+    [&output.stderr, &output.stdout]
+        .into_iter()
+        .map(|s| s.trim())
+        .find(|s| !s.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| match output.code {
+            Some(code) => format!("exit code {code}"),
+            None => String::from("terminated by signal"),
+        })
+}
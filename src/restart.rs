@@ -0,0 +1,180 @@
+use crate::scan::glob_set;
+use globset::GlobSet;
+use serde::Deserialize;
+use std::{collections::BTreeSet, path::Path};
+
+/// What to do on the device once a file it depends on was replaced.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum RestartAction {
+    /// Stop and start an init service with `begetctl`.
+    RestartService { service: String },
+    /// Kill a process, leaving it to init to start it again.
+    Kill { process: String },
+    /// Reboot the device, only carried out with `--reboot`.
+    Reboot,
+}
+
+/// Device path globs and the action taken when one of them was pushed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestartRule {
+    pub paths: Vec<String>,
+    #[serde(flatten)]
+    pub action: RestartAction,
+}
+
+impl RestartAction {
+    /// Shell command carrying out the action on the device, `None` for a reboot,
+    /// which goes through `hdc target boot`.
+    pub fn command(&self) -> Option<String> {
+        match self {
+            RestartAction::RestartService { service } => Some(format!(
+                "begetctl stop_service {service}; begetctl start_service {service}"
+            )),
+            RestartAction::Kill { process } => Some(format!("killall {process}")),
+            RestartAction::Reboot => None,
+        }
+    }
+}
+
+/// Rules used when the config doesn't give any.
+pub fn default_restart_rules() -> Vec<RestartRule> {
+    let rule = |paths: &[&str], action| RestartRule {
+        paths: paths.iter().map(|path| path.to_string()).collect(),
+        action,
+    };
+    let service = |name: &str| RestartAction::RestartService {
+        service: name.to_owned(),
+    };
+    vec![
+        rule(
+            &["/system/bin/samgr", "/system/lib*/libsamgr*.z.so"],
+            RestartAction::Reboot,
+        ),
+        rule(&["/system/etc/init/*.cfg"], RestartAction::Reboot),
+        rule(&["/system/bin/foundation"], service("foundation")),
+        rule(
+            &[
+                "/system/bin/render_service",
+                "/system/lib*/librender_service*.z.so",
+            ],
+            service("render_service"),
+        ),
+        rule(&["/system/bin/hilogd"], service("hilogd")),
+        rule(
+            &["/system/bin/hiview"],
+            RestartAction::Kill {
+                process: String::from("hiview"),
+            },
+        ),
+    ]
+}
+
+/// Restart rules with their globs compiled.
+pub struct Restarts {
+    rules: Vec<(GlobSet, RestartAction)>,
+}
+
+impl Restarts {
+    pub fn new(rules: &[RestartRule]) -> Result<Self, globset::Error> {
+        Ok(Restarts {
+            rules: rules
+                .iter()
+                .map(|rule| Ok((glob_set(&rule.paths)?, rule.action.clone())))
+                .collect::<Result<_, globset::Error>>()?,
+        })
+    }
+
+    /// Actions due after writing `device_paths`, each one once. A reboot restarts
+    /// everything, so it replaces all other actions.
+    pub fn actions<'a>(
+        &self,
+        device_paths: impl IntoIterator<Item = &'a Path>,
+    ) -> BTreeSet<&RestartAction> {
+        let mut actions = BTreeSet::new();
+        for device_path in device_paths {
+            for (globs, action) in &self.rules {
+                if globs.is_match(device_path) {
+                    actions.insert(action);
+                }
+            }
+        }
+        if actions.contains(&RestartAction::Reboot) {
+            actions.retain(|action| **action == RestartAction::Reboot);
+        }
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions<'a>(restarts: &'a Restarts, paths: &[&'a str]) -> Vec<&'a RestartAction> {
+        restarts
+            .actions(paths.iter().map(Path::new))
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn actions_are_taken_once_per_match() {
+        let restarts = Restarts::new(&default_restart_rules()).unwrap();
+        let render_service = RestartAction::RestartService {
+            service: String::from("render_service"),
+        };
+        let hiview = RestartAction::Kill {
+            process: String::from("hiview"),
+        };
+        assert_eq!(
+            actions(
+                &restarts,
+                &[
+                    "/system/bin/render_service",
+                    "/system/lib64/librender_service_base.z.so",
+                    "/system/bin/hiview",
+                    "/system/lib64/libunrelated.z.so",
+                ]
+            ),
+            [&render_service, &hiview]
+        );
+        assert!(actions(&restarts, &["/system/lib64/libunrelated.z.so"]).is_empty());
+        // * doesn't cross directories
+        assert!(actions(&restarts, &["/system/etc/init/sub/foo.cfg"]).is_empty());
+    }
+
+    #[test]
+    fn reboot_replaces_other_actions() {
+        let restarts = Restarts::new(&default_restart_rules()).unwrap();
+        assert_eq!(
+            actions(
+                &restarts,
+                &["/system/bin/foundation", "/system/lib/libsamgr_proxy.z.so"]
+            ),
+            [&RestartAction::Reboot]
+        );
+    }
+
+    #[test]
+    fn configured_rules_parse() {
+        #[derive(Deserialize)]
+        struct Config {
+            restart: Vec<RestartRule>,
+        }
+        let config: Config = toml::from_str(
+            r#"
+            [[restart]]
+            paths = ["/vendor/bin/audio_host"]
+            action = "kill"
+            process = "audio_host"
+            "#,
+        )
+        .unwrap();
+        let restarts = Restarts::new(&config.restart).unwrap();
+        let kill = RestartAction::Kill {
+            process: String::from("audio_host"),
+        };
+        assert_eq!(actions(&restarts, &["/vendor/bin/audio_host"]), [&kill]);
+        assert_eq!(kill.command().unwrap(), "killall audio_host");
+    }
+}
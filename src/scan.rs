@@ -0,0 +1,216 @@
+use crate::config::ScanConfig;
+use clap::ValueEnum;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Scan roots used when neither the config nor the command line set any.
+pub const DEFAULT_SCAN_ROOTS: [&str; 21] = [
+    "applications",
+    "arkcompiler",
+    "base",
+    "build",
+    "commonlibrary",
+    "cpp",
+    "developtools",
+    "device",
+    "domains",
+    "drivers",
+    "foundation",
+    "isa",
+    "kernel",
+    "libpandabase",
+    "out",
+    "test",
+    "third_party",
+    "vendor",
+    "communication",
+    "multimedia",
+    "distributedhardware",
+];
+
+/// Where build files are looked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanMode {
+    /// Walk the scan roots of the source checkout and map files to the device by name.
+    #[default]
+    Tree,
+    /// Walk only the build package directory, whose layout is the device's.
+    Packages,
+}
+
+/// Walks the build directory, applying include and exclude globs.
+pub struct Scanner {
+    mode: ScanMode,
+    roots: Vec<String>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Scanner {
+    pub fn new(config: &ScanConfig) -> Result<Self, globset::Error> {
+        let roots = match &config.roots {
+            Some(roots) => roots.clone(),
+            None => DEFAULT_SCAN_ROOTS.map(String::from).to_vec(),
+        };
+        let include = if config.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&config.include)?)
+        };
+        Ok(Scanner {
+            mode: config.mode.unwrap_or_default(),
+            roots,
+            include,
+            exclude: glob_set(&config.exclude)?,
+        })
+    }
+
+    /// Every file of `build_dir` that passes the globs: those under the scan roots,
+    /// or under `package_dir` in [`ScanMode::Packages`].
+    ///
+    /// Globs are matched against paths relative to `build_dir`. A directory matching
+    /// an exclude glob is not walked at all.
+    pub fn files(&self, build_dir: &Path, package_dir: &Path) -> Vec<PathBuf> {
+        let roots = match self.mode {
+            ScanMode::Tree => self.roots.iter().map(|root| build_dir.join(root)).collect(),
+            ScanMode::Packages => vec![package_dir.to_path_buf()],
+        };
+        let relative = |path: &Path| path.strip_prefix(build_dir).unwrap_or(path).to_path_buf();
+
+        roots
+            .into_iter()
+            .filter(|path| path.exists())
+            .flat_map(|path| {
+                WalkDir::new(path)
+                    .into_iter()
+                    .filter_entry(|entry| !self.exclude.is_match(relative(entry.path())))
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_file())
+                    .filter(|entry| {
+                        self.include
+                            .as_ref()
+                            .is_none_or(|include| include.is_match(relative(entry.path())))
+                    })
+                    .map(|entry| entry.into_path())
+            })
+            .collect()
+    }
+}
+
+/// Globs matching whole path components, `*` doesn't cross `/`.
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(glob(pattern)?);
+    }
+    builder.build()
+}
+
+fn glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn scan(build: &TempDir, config: ScanConfig) -> Vec<String> {
+        let package_dir = build.path().join("packages/phone");
+        let mut files: Vec<_> = Scanner::new(&config)
+            .unwrap()
+            .files(build.path(), &package_dir)
+            .into_iter()
+            .map(|file| {
+                file.strip_prefix(build.path())
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn strings(globs: &[&str]) -> Vec<String> {
+        globs.iter().map(|glob| glob.to_string()).collect()
+    }
+
+    fn build() -> TempDir {
+        let build = TempDir::new();
+        for file in [
+            "out/rk3568/libfoo.z.so",
+            "out/rk3568/obj/foo.o",
+            "out/rk3568/lib.unstripped/libfoo.z.so",
+            "base/foo.z.so",
+            "docs/foo.z.so",
+            "packages/phone/system/lib64/libfoo.z.so",
+        ] {
+            build.write(file, b"");
+        }
+        build
+    }
+
+    #[test]
+    fn tree_mode_walks_only_the_roots() {
+        let build = build();
+        let config = ScanConfig {
+            roots: Some(strings(&["out", "base", "missing"])),
+            ..ScanConfig::default()
+        };
+        assert_eq!(
+            scan(&build, config),
+            [
+                "base/foo.z.so",
+                "out/rk3568/lib.unstripped/libfoo.z.so",
+                "out/rk3568/libfoo.z.so",
+                "out/rk3568/obj/foo.o",
+            ]
+        );
+    }
+
+    #[test]
+    fn globs_match_relative_to_the_build_directory() {
+        let build = build();
+        let config = ScanConfig {
+            roots: Some(strings(&["out", "base"])),
+            include: strings(&["out/*/*.so", "base/*.so"]),
+            ..ScanConfig::default()
+        };
+        // * doesn't cross /, so files further down don't match out/*/*.so
+        assert_eq!(
+            scan(&build, config),
+            ["base/foo.z.so", "out/rk3568/libfoo.z.so"]
+        );
+    }
+
+    #[test]
+    fn excluded_directories_are_not_walked() {
+        let build = build();
+        let config = ScanConfig {
+            roots: Some(strings(&["out"])),
+            // a glob of the files would not match the directory, so it must be skipped whole
+            include: strings(&["**/*.so", "**/*.o"]),
+            exclude: strings(&["out/*/lib.unstripped", "**/obj"]),
+            ..ScanConfig::default()
+        };
+        assert_eq!(scan(&build, config), ["out/rk3568/libfoo.z.so"]);
+    }
+
+    #[test]
+    fn packages_mode_walks_only_the_package_directory() {
+        let build = build();
+        let config = ScanConfig {
+            mode: Some(ScanMode::Packages),
+            roots: Some(strings(&["out"])),
+            ..ScanConfig::default()
+        };
+        assert_eq!(
+            scan(&build, config),
+            ["packages/phone/system/lib64/libfoo.z.so"]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    record::RecordScope,
+    workdir::{file_name_safe, write_atomic},
+};
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+/// Directory of the working directory holding one snapshot per push.
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// Directory of a snapshot holding the device copies, laid out by device path.
+const FILES_DIR: &str = "files";
+
+/// Snapshots kept per device, older ones are removed as new ones are taken.
+pub const SNAPSHOTS_KEPT: usize = 10;
+
+/// State of a device path before a push overwrote it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub device_path: PathBuf,
+    /// Manifest keys of the build files pushed to this path.
+    pub build_files: Vec<String>,
+    /// Whether the path existed on the device, it is removed on rollback otherwise.
+    pub existed: bool,
+    /// Whether the device copy was saved, it can't be restored otherwise.
+    pub saved: bool,
+}
+
+/// Device files a push was about to overwrite.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub push_id: String,
+    pub connectkey: String,
+    pub build_dir: PathBuf,
+    pub build_package_dir: String,
+    pub created: String,
+    pub files: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    pub fn new(push_id: &str, scope: &RecordScope) -> Self {
+        Snapshot {
+            push_id: push_id.to_owned(),
+            connectkey: scope.connectkey.clone(),
+            build_dir: scope.build_dir.clone(),
+            build_package_dir: scope.build_package_dir.clone(),
+            created: Utc::now().to_rfc3339(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn scope(&self) -> RecordScope {
+        RecordScope {
+            connectkey: self.connectkey.clone(),
+            build_dir: self.build_dir.clone(),
+            build_package_dir: self.build_package_dir.clone(),
+        }
+    }
+
+    pub fn dir(workdir: &Path, push_id: &str) -> PathBuf {
+        workdir.join(SNAPSHOT_DIR).join(push_id)
+    }
+
+    /// Where the device copy of `device_path` is kept.
+    pub fn file_path(workdir: &Path, push_id: &str, device_path: &Path) -> PathBuf {
+        Self::dir(workdir, push_id)
+            .join(FILES_DIR)
+            .join(device_path.strip_prefix("/").unwrap_or(device_path))
+    }
+
+    pub fn save(&self, workdir: &Path) -> Result<()> {
+        let dir = Self::dir(workdir, &self.push_id);
+        fs::create_dir_all(&dir)?;
+        write_atomic(
+            &dir.join(SNAPSHOT_FILE),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )
+    }
+
+    pub fn load(workdir: &Path, push_id: &str) -> Result<Self> {
+        let path = Self::dir(workdir, push_id).join(SNAPSHOT_FILE);
+        let content = fs::read(&path).map_err(|error| match error.kind() {
+            ErrorKind::NotFound => Error::new(
+                ErrorKind::NotFound,
+                format!("no snapshot of push {push_id}"),
+            ),
+            _ => error,
+        })?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Every snapshot in the working directory, oldest first.
+    pub fn list(workdir: &Path) -> Vec<Self> {
+        let Ok(dirs) = fs::read_dir(workdir.join(SNAPSHOT_DIR)) else {
+            return Vec::new();
+        };
+        let mut snapshots: Vec<_> = dirs
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let push_id = entry.file_name().to_string_lossy().into_owned();
+                Self::load(workdir, &push_id)
+                    .inspect_err(|error| warn!("skip snapshot {push_id}: {error}"))
+                    .ok()
+            })
+            .collect();
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created));
+        snapshots
+    }
+
+    /// Remove the snapshots of `connect_key` but the newest [`SNAPSHOTS_KEPT`],
+    /// returning how many were removed.
+    pub fn prune(workdir: &Path, connect_key: &str) -> usize {
+        let snapshots: Vec<_> = Self::list(workdir)
+            .into_iter()
+            .filter(|snapshot| snapshot.connectkey == connect_key)
+            .collect();
+        let excess = snapshots.len().saturating_sub(SNAPSHOTS_KEPT);
+        snapshots[..excess]
+            .iter()
+            .filter(|snapshot| {
+                fs::remove_dir_all(Self::dir(workdir, &snapshot.push_id))
+                    .inspect_err(|error| {
+                        warn!("fail to remove snapshot {}: {error}", snapshot.push_id)
+                    })
+                    .is_ok()
+            })
+            .count()
+    }
+}
+
+/// Id of a push to `connect_key` starting now.
+pub fn new_push_id(connect_key: &str) -> String {
+    format!(
+        "{}-{}",
+        Utc::now().format("%Y%m%d-%H%M%S%3f"),
+        file_name_safe(connect_key)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn prune_keeps_the_newest_snapshots_of_the_device() {
+        let workdir = TempDir::new();
+        let build = TempDir::new();
+        for (connect_key, count) in [("a", SNAPSHOTS_KEPT + 2), ("b", 1)] {
+            let scope = RecordScope::new(connect_key, build.path(), "packages/phone");
+            for n in 0..count {
+                let mut snapshot = Snapshot::new(&format!("{connect_key}-{n:02}"), &scope);
+                snapshot.created = format!("2024-01-01T00:00:{n:02}+00:00");
+                snapshot.save(workdir.path()).unwrap();
+            }
+        }
+
+        assert_eq!(Snapshot::prune(workdir.path(), "a"), 2);
+        let push_ids: Vec<_> = Snapshot::list(workdir.path())
+            .into_iter()
+            .map(|snapshot| snapshot.push_id)
+            .collect();
+        assert_eq!(push_ids.len(), SNAPSHOTS_KEPT + 1);
+        assert!(!push_ids.contains(&String::from("a-00")));
+        assert!(!push_ids.contains(&String::from("a-01")));
+        assert!(push_ids.contains(&String::from("b-00")));
+    }
+}
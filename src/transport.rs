@@ -0,0 +1,377 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+/// Printed by [`DeviceTransport::shell_checked`] commands that succeeded.
+pub const SHELL_SUCCEEDED: &str = "oh-pusher-ok";
+
+/// A single operation issued against the device side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportCall {
+    Remount {
+        connect_key: String,
+        mount_point: String,
+    },
+    SendFile {
+        connect_key: String,
+        local: PathBuf,
+        remote: PathBuf,
+    },
+    RecvFile {
+        connect_key: String,
+        remote: PathBuf,
+        local: PathBuf,
+    },
+    Shell {
+        connect_key: String,
+        command: Vec<String>,
+    },
+    Reboot {
+        connect_key: String,
+    },
+    ListTargets,
+}
+
+impl TransportCall {
+    /// Arguments handed to `hdc` to carry out this call.
+    pub fn hdc_args(&self) -> Vec<String> {
+        match self {
+            TransportCall::Remount {
+                connect_key,
+                mount_point,
+            } => vec![
+                "-t".into(),
+                connect_key.clone(),
+                "shell".into(),
+                "mount".into(),
+                "-o".into(),
+                "remount,rw".into(),
+                mount_point.clone(),
+            ],
+            TransportCall::SendFile {
+                connect_key,
+                local,
+                remote,
+            } => vec![
+                "-t".into(),
+                connect_key.clone(),
+                "file".into(),
+                "send".into(),
+                local.to_string_lossy().into_owned(),
+                remote.to_string_lossy().into_owned(),
+            ],
+            TransportCall::RecvFile {
+                connect_key,
+                remote,
+                local,
+            } => vec![
+                "-t".into(),
+                connect_key.clone(),
+                "file".into(),
+                "recv".into(),
+                remote.to_string_lossy().into_owned(),
+                local.to_string_lossy().into_owned(),
+            ],
+            TransportCall::Shell {
+                connect_key,
+                command,
+            } => ["-t".into(), connect_key.clone(), "shell".into()]
+                .into_iter()
+                .chain(command.iter().cloned())
+                .collect(),
+            TransportCall::Reboot { connect_key } => vec![
+                "-t".into(),
+                connect_key.clone(),
+                "target".into(),
+                "boot".into(),
+            ],
+            TransportCall::ListTargets => vec!["list".into(), "targets".into()],
+        }
+    }
+
+    /// The `hdc` invocation of this call as it would be typed in a shell.
+    pub fn hdc_command_line(&self) -> String {
+        std::iter::once(String::from("hdc"))
+            .chain(self.hdc_args().iter().map(|arg| shell_quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Quote `arg` for a POSIX shell, leaving plain words as they are.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./,:=+@%".contains(c));
+    if plain {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Exit status and captured output of a transport call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallOutput {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CallOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Everything the pusher needs from a device connection.
+///
+/// Implementors only provide [`DeviceTransport::execute`]; the helpers build the
+/// matching [`TransportCall`].
+pub trait DeviceTransport: Send + Sync {
+    fn execute(&self, call: TransportCall) -> io::Result<CallOutput>;
+
+    fn remount(&self, connect_key: &str, mount_point: &str) -> io::Result<CallOutput> {
+        self.execute(TransportCall::Remount {
+            connect_key: connect_key.to_owned(),
+            mount_point: mount_point.to_owned(),
+        })
+    }
+
+    fn send_file(&self, connect_key: &str, local: &Path, remote: &Path) -> io::Result<CallOutput> {
+        self.execute(TransportCall::SendFile {
+            connect_key: connect_key.to_owned(),
+            local: local.to_path_buf(),
+            remote: remote.to_path_buf(),
+        })
+    }
+
+    fn recv_file(&self, connect_key: &str, remote: &Path, local: &Path) -> io::Result<CallOutput> {
+        self.execute(TransportCall::RecvFile {
+            connect_key: connect_key.to_owned(),
+            remote: remote.to_path_buf(),
+            local: local.to_path_buf(),
+        })
+    }
+
+    fn shell(&self, connect_key: &str, command: &[&str]) -> io::Result<CallOutput> {
+        self.execute(TransportCall::Shell {
+            connect_key: connect_key.to_owned(),
+            command: command.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    /// Run `command` in the device shell, the output only counts as a success if
+    /// the command did. `hdc shell` doesn't pass the exit code on, so the command
+    /// prints [`SHELL_SUCCEEDED`] once it succeeded, which is taken out of the output.
+    fn shell_checked(&self, connect_key: &str, command: &str) -> io::Result<CallOutput> {
+        let mut output = self.shell(
+            connect_key,
+            &[&format!("{command} && echo {SHELL_SUCCEEDED}")],
+        )?;
+        let succeeded = output
+            .stdout
+            .lines()
+            .any(|line| line.trim() == SHELL_SUCCEEDED);
+        output.stdout = output
+            .stdout
+            .lines()
+            .filter(|line| line.trim() != SHELL_SUCCEEDED)
+            .map(|line| format!("{line}\n"))
+            .collect();
+        if output.success() && !succeeded {
+            output.code = Some(1);
+        }
+        Ok(output)
+    }
+
+    fn reboot(&self, connect_key: &str) -> io::Result<CallOutput> {
+        self.execute(TransportCall::Reboot {
+            connect_key: connect_key.to_owned(),
+        })
+    }
+
+    fn list_targets(&self) -> io::Result<Vec<String>> {
+        let output = self.execute(TransportCall::ListTargets)?;
+        Ok(output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && *line != "[Empty]")
+            .map(String::from)
+            .collect())
+    }
+}
+
+/// Talks to devices through the `hdc` command line tool.
+pub struct HdcTransport {
+    program: String,
+}
+
+impl Default for HdcTransport {
+    fn default() -> Self {
+        HdcTransport {
+            program: String::from("hdc"),
+        }
+    }
+}
+
+impl DeviceTransport for HdcTransport {
+    fn execute(&self, call: TransportCall) -> io::Result<CallOutput> {
+        let output = Command::new(&self.program).args(call.hdc_args()).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        // hdc reports many failures on stdout while still exiting with 0
+        let code = match output.status.code() {
+            Some(0) if stdout.contains("[Fail]") => Some(1),
+            code => code,
+        };
+        Ok(CallOutput {
+            code,
+            stdout,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// Scripted answer of a [`FakeTransport`] to the calls it matches, `None` for others.
+type Responder = Box<dyn Fn(&TransportCall) -> Option<io::Result<CallOutput>> + Send + Sync>;
+
+/// In-process transport that records every call and reports success, unless a
+/// responder scripted another answer.
+///
+/// Lets the push logic run without a device attached.
+#[derive(Default)]
+pub struct FakeTransport {
+    calls: Mutex<Vec<TransportCall>>,
+    targets: Vec<String>,
+    responders: Mutex<Vec<Responder>>,
+}
+
+impl FakeTransport {
+    pub fn with_targets(targets: &[&str]) -> Self {
+        FakeTransport {
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            ..FakeTransport::default()
+        }
+    }
+
+    /// Answer the calls `responder` returns `Some` for with its result. Responders
+    /// added later take precedence.
+    pub fn respond(
+        &self,
+        responder: impl Fn(&TransportCall) -> Option<io::Result<CallOutput>> + Send + Sync + 'static,
+    ) {
+        self.responders.lock().unwrap().push(Box::new(responder));
+    }
+
+    pub fn calls(&self) -> Vec<TransportCall> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl DeviceTransport for FakeTransport {
+    fn execute(&self, call: TransportCall) -> io::Result<CallOutput> {
+        let scripted = self
+            .responders
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find_map(|responder| responder(&call));
+        let stdout = match &call {
+            TransportCall::ListTargets => self.targets.join("\n"),
+            // checked commands succeed like any other call
+            TransportCall::Shell { command, .. }
+                if command.last().is_some_and(|command| {
+                    command.ends_with(&format!("&& echo {SHELL_SUCCEEDED}"))
+                }) =>
+            {
+                format!("{SHELL_SUCCEEDED}\n")
+            }
+            _ => String::new(),
+        };
+        self.calls.lock().unwrap().push(call);
+        scripted.unwrap_or(Ok(CallOutput {
+            code: Some(0),
+            stdout,
+            stderr: String::new(),
+        }))
+    }
+}
+
+/// Prints the `hdc` command of every call instead of running it, reporting success.
+#[derive(Default)]
+pub struct DryRunTransport {
+    /// Print to stderr, leaving stdout to machine readable output.
+    pub to_stderr: bool,
+}
+
+impl DeviceTransport for DryRunTransport {
+    fn execute(&self, call: TransportCall) -> io::Result<CallOutput> {
+        if self.to_stderr {
+            eprintln!("{}", call.hdc_command_line());
+        } else {
+            println!("{}", call.hdc_command_line());
+        }
+        Ok(CallOutput {
+            code: Some(0),
+            ..CallOutput::default()
+        })
+    }
+
+    /// Prints the command as given, nothing runs to print the success marker.
+    fn shell_checked(&self, connect_key: &str, command: &str) -> io::Result<CallOutput> {
+        self.shell(connect_key, &[command])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answering(stdout: &'static str) -> FakeTransport {
+        let transport = FakeTransport::default();
+        transport.respond(move |_| {
+            Some(Ok(CallOutput {
+                code: Some(0),
+                stdout: stdout.to_owned(),
+                stderr: String::new(),
+            }))
+        });
+        transport
+    }
+
+    #[test]
+    fn shell_checked_succeeds_only_with_the_marker() {
+        let transport = answering("done\noh-pusher-ok\n");
+        let output = transport.shell_checked("device", "true").unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, "done\n");
+        assert_eq!(
+            transport.calls(),
+            [TransportCall::Shell {
+                connect_key: String::from("device"),
+                command: vec![format!("true && echo {SHELL_SUCCEEDED}")],
+            }]
+        );
+
+        // hdc exits with 0 whether or not the command failed
+        let output = answering("rm: /system/foo: Read-only file system\n")
+            .shell_checked("device", "rm -f /system/foo")
+            .unwrap();
+        assert!(!output.success());
+        assert_eq!(output.stdout, "rm: /system/foo: Read-only file system\n");
+    }
+
+    #[test]
+    fn fake_checked_commands_succeed_by_default() {
+        assert!(FakeTransport::default()
+            .shell_checked("device", "true")
+            .unwrap()
+            .success());
+    }
+}
@@ -0,0 +1,70 @@
+use log::info;
+use std::{
+    env,
+    fs::{self, File, TryLockError},
+    io::{Result, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const LOCK_FILE: &str = ".lock";
+
+pub fn establish_workdir() -> Result<PathBuf> {
+    let xdg_conf_home = env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
+        let home = env::var("HOME").expect("HOME not set");
+        format!("{home}/.config")
+    });
+    let workdir = PathBuf::from(xdg_conf_home).join("hdc_push_buildfiles");
+    fs::create_dir_all(&workdir)?;
+    Ok(workdir)
+}
+
+/// Advisory lock on the working directory, released when dropped.
+pub struct WorkdirLock {
+    _file: File,
+}
+
+impl WorkdirLock {
+    /// Block until no other run holds the working directory.
+    pub fn acquire(workdir: &Path) -> Result<Self> {
+        let file = File::create(workdir.join(LOCK_FILE))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                info!("Waiting for another run to release {}", workdir.display());
+                file.lock()?;
+            }
+            Err(TryLockError::Error(error)) => return Err(error),
+        }
+        Ok(WorkdirLock { _file: file })
+    }
+}
+
+/// Replace `path` with `contents` so readers see either the old or the new file,
+/// never a partial write.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    // threads of one run may write the same file, each one needs its own temp file
+    static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut tmp_file = File::create(&tmp_path)?;
+    tmp_file.write_all(contents)?;
+    tmp_file.sync_all()?;
+    drop(tmp_file);
+
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+/// `name` with everything but ASCII letters and digits replaced, usable in file names.
+pub fn file_name_safe(name: &str) -> String {
+    name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}